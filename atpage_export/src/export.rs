@@ -0,0 +1,492 @@
+use crate::links;
+use anyhow::{anyhow, Context, Result};
+use atpage_renderer::{
+    atproto::{self, Error},
+    host::Native,
+    identity, records, sniff,
+};
+use serde_json::Value;
+use shared::atproto::{ATURL, CHUNKED_COLLECTION, PAGE_COLLECTION, SITE_COLLECTION, SITE_RKEY};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Directory blobs and chunked files referenced by /at/ URLs are written to, by CID.
+const BLOBS_DIR: &str = "at/blobs";
+
+/// Directory pages missing from the site's path index are written to, by collection and key.
+const PAGES_DIR: &str = "at";
+
+/// Target is something published in the site's repo that can be exported to a file.
+#[derive(Hash, PartialEq, Eq, Clone, Debug)]
+enum Target {
+    Page { collection: String, rkey: String },
+    Blob(String),
+    Chunked(String),
+}
+
+/// Stats counts what an export wrote.
+pub struct Stats {
+    pub pages: usize,
+    pub files: usize,
+}
+
+/// Export writes a site and everything its pages link to in its repo to a directory.
+pub struct Export {
+    host: Native,
+    did: String,
+    pds: String,
+    site: Value,
+    out: PathBuf,
+
+    /// Handles and DIDs /at/ links may point to the site's repo by, as resolved so far.
+    ids: HashMap<String, bool>,
+
+    /// Path every target was, or is going to be, written to, relative to out.
+    paths: HashMap<Target, String>,
+
+    queue: VecDeque<Target>,
+    done: HashSet<Target>,
+    failed: usize,
+    stats: Stats,
+}
+
+/// err turns renderer errors, which aren't thread-safe, into anyhow ones.
+fn err(e: Error) -> anyhow::Error {
+    anyhow!("{}", e)
+}
+
+impl Export {
+    /// new resolves the site at_uri belongs to, and queues all of its pages and assets, plus the
+    /// page at_uri points to if any.
+    pub async fn new(host: Native, at_uri: &str, out: PathBuf) -> Result<Self> {
+        let uri = at_uri.trim_start_matches("at://").trim_end_matches('/');
+        let id = uri.split('/').next().unwrap_or_default();
+
+        let identity = identity::solve(&host, id)
+            .await
+            .map_err(err)
+            .with_context(|| format!("cannot resolve {}", id))?;
+
+        let site = match atproto::fetch_record(
+            &host,
+            identity.pds.clone(),
+            identity.did.clone(),
+            SITE_COLLECTION.to_string(),
+            SITE_RKEY.to_string(),
+        )
+        .await
+        {
+            Ok(r) => r.value,
+            Err(Error::NoRecordFound(_)) => {
+                log::warn!("{} has no site record, only following links", id);
+                Value::Null
+            }
+            Err(e) => return Err(err(e).context("cannot fetch the site record")),
+        };
+
+        let mut export = Export {
+            host,
+            did: identity.did.clone(),
+            pds: identity.pds,
+            site,
+            out,
+            ids: HashMap::from([(id.to_lowercase(), true), (identity.did, true)]),
+            paths: HashMap::new(),
+            queue: VecDeque::new(),
+            done: HashSet::new(),
+            failed: 0,
+            stats: Stats { pages: 0, files: 0 },
+        };
+
+        export.queue_site();
+
+        if uri.contains('/') {
+            let atu = ATURL::try_from(uri.to_string()).map_err(|e| anyhow!("{}", e))?;
+            if let Some(target) = export.at_target(&atu).await {
+                export.queue.push_back(target);
+            }
+        }
+
+        Ok(export)
+    }
+
+    /// queue_site queues the pages of the site's path index and its standalone assets, each to be
+    /// written at the path it was published from.
+    fn queue_site(&mut self) {
+        let pages = self.site.get("pages").and_then(|e| e.as_array());
+        for page in pages.into_iter().flatten() {
+            let path = page.get("path").and_then(|e| e.as_str());
+            let rkey = page.get("rkey").and_then(|e| e.as_str());
+
+            if let (Some(path), Some(rkey)) = (path, rkey) {
+                let target = Target::Page {
+                    collection: PAGE_COLLECTION.to_string(),
+                    rkey: rkey.to_string(),
+                };
+
+                self.paths
+                    .insert(target.clone(), path.trim_start_matches('/').to_string());
+                self.queue.push_back(target);
+            }
+        }
+
+        let assets = self.site.get("assets").and_then(|e| e.as_array());
+        for path in assets
+            .into_iter()
+            .flatten()
+            .filter_map(|a| a.get("path").and_then(|e| e.as_str()))
+        {
+            let target = match records::asset(&self.site, path) {
+                Some(Ok(records::Asset::Blob(cid))) => Target::Blob(cid),
+                Some(Ok(records::Asset::Chunked(rkey))) => Target::Chunked(rkey),
+                _ => {
+                    log::warn!("malformed asset {}, skipping", path);
+                    continue;
+                }
+            };
+
+            self.paths
+                .insert(target.clone(), path.trim_start_matches('/').to_string());
+            self.queue.push_back(target);
+        }
+    }
+
+    /// run exports everything queued, and everything linked from it in turn.
+    pub async fn run(mut self) -> Result<Stats> {
+        while let Some(target) = self.queue.pop_front() {
+            if !self.done.insert(target.clone()) {
+                continue;
+            }
+
+            if let Err(e) = self.export(&target).await {
+                log::error!("cannot export {:?}: {:#}", target, e);
+                self.failed += 1;
+            }
+        }
+
+        match self.failed {
+            0 => Ok(self.stats),
+            n => Err(anyhow!("{} pages or files couldn't be exported", n)),
+        }
+    }
+
+    async fn export(&mut self, target: &Target) -> Result<()> {
+        match target {
+            Target::Page { collection, rkey } => {
+                self.page(target, collection.clone(), rkey.clone()).await
+            }
+            _ => self.file(target).await,
+        }
+    }
+
+    /// file writes the blob or chunked file target.
+    async fn file(&mut self, target: &Target) -> Result<()> {
+        match target {
+            Target::Blob(cid) => {
+                let (data, mime_type) = self.blob(cid.clone()).await?;
+                let path = self.file_path(target, cid, &mime_type);
+
+                self.write(&path, &data)?;
+            }
+            Target::Chunked(rkey) => self.chunked(target, rkey).await?,
+            Target::Page { .. } => unreachable!("pages aren't files"),
+        }
+
+        self.stats.files += 1;
+
+        Ok(())
+    }
+
+    /// chunked writes the chunked file at rkey a chunk at a time, checking it's as long as its
+    /// record says.
+    async fn chunked(&mut self, target: &Target, rkey: &str) -> Result<()> {
+        let record = self
+            .record(CHUNKED_COLLECTION.to_string(), rkey.to_string())
+            .await?;
+        let file = records::chunked(&record, rkey).map_err(err)?;
+
+        let path = self.file_path(target, rkey, &file.mime_type);
+        let (dest, mut out) = self.create(&path)?;
+
+        let res = match self.chunks(&mut out, &dest, file.chunks).await {
+            Ok(written) if written != file.size => Err(anyhow!(
+                "{} is {} bytes long, its record says {}",
+                path,
+                written,
+                file.size
+            )),
+            res => res.map(|_| ()),
+        };
+
+        // a partial file would be served as if it were whole
+        if res.is_err() {
+            drop(out);
+            let _ = std::fs::remove_file(&dest);
+        }
+        res?;
+
+        log::info!("Exported {}", path);
+
+        Ok(())
+    }
+
+    /// chunks writes the blobs in chunks to out, in order, and returns how many bytes they hold.
+    async fn chunks(
+        &self,
+        out: &mut File,
+        dest: &Path,
+        chunks: Vec<records::Chunk>,
+    ) -> Result<u64> {
+        let mut written = 0;
+
+        for chunk in chunks {
+            let (data, _) = self.blob(chunk.cid).await?;
+            written += data.len() as u64;
+
+            out.write_all(&data)
+                .with_context(|| format!("cannot write {}", dest.display()))?;
+        }
+
+        Ok(written)
+    }
+
+    /// page writes the page at collection/rkey, with its links to the site rewritten to the
+    /// exported files, and queues what they point to.
+    async fn page(&mut self, target: &Target, collection: String, rkey: String) -> Result<()> {
+        let page = self.record(collection, rkey.clone()).await?;
+        let content = records::page_content(&page, &rkey).map_err(err)?;
+
+        let path = self.page_path(target);
+        let (data, _) = self.blob(content.cid).await?;
+
+        let is_html = content
+            .mime_type
+            .as_deref()
+            .map(|m| m.starts_with("text/html"))
+            .unwrap_or(true);
+
+        let data = match is_html {
+            true => {
+                let html = String::from_utf8(data).context("page isn't UTF-8")?;
+                self.rewrite(&path, &html).await?.into_bytes()
+            }
+            false => data,
+        };
+
+        self.write(&path, &data)?;
+        self.stats.pages += 1;
+
+        Ok(())
+    }
+
+    /// rewrite returns the HTML page content, about to be written at path, with every link to
+    /// the site pointing to the exported files instead.
+    async fn rewrite(&mut self, path: &str, content: &str) -> Result<String> {
+        let mut replacements = HashMap::new();
+
+        for link in links::collect(content)? {
+            let (link_path, rest) = links::split(&link);
+
+            let target = match self.link_target(link_path).await {
+                Some(t) => t,
+                None => continue,
+            };
+
+            // files are named after their type, which is only known once fetched
+            if !matches!(target, Target::Page { .. }) && !self.done.contains(&target) {
+                self.done.insert(target.clone());
+
+                if let Err(e) = self.file(&target).await {
+                    log::error!("cannot export {:?}: {:#}", target, e);
+                    self.failed += 1;
+                    continue;
+                }
+            }
+
+            let to = match &target {
+                Target::Page { .. } => self.page_path(&target),
+                _ => match self.paths.get(&target) {
+                    Some(p) => p.clone(),
+                    None => continue,
+                },
+            };
+
+            if matches!(target, Target::Page { .. }) {
+                self.queue.push_back(target);
+            }
+
+            replacements.insert(
+                link.clone(),
+                format!("{}{}", links::relative(path, &to), rest),
+            );
+        }
+
+        Ok(links::rewrite(content, &replacements)?)
+    }
+
+    /// link_target returns what link, the path part of a link found in a page, points to in the
+    /// site's repo. Relative links and links elsewhere are left alone.
+    async fn link_target(&mut self, link: &str) -> Option<Target> {
+        if let Some(at) = link.strip_prefix("/at/") {
+            let atu = ATURL::try_from(at.to_string()).ok()?;
+            return self.at_target(&atu).await;
+        }
+
+        if !link.starts_with('/') || link.starts_with("//") {
+            return None;
+        }
+
+        match records::path(&self.site, link).ok()? {
+            records::SitePath::Page(rkey) => Some(Target::Page {
+                collection: PAGE_COLLECTION.to_string(),
+                rkey,
+            }),
+            records::SitePath::Asset(records::Asset::Blob(cid)) => Some(Target::Blob(cid)),
+            records::SitePath::Asset(records::Asset::Chunked(rkey)) => Some(Target::Chunked(rkey)),
+        }
+    }
+
+    /// at_target returns what atu points to, if it's in the site's repo.
+    async fn at_target(&mut self, atu: &ATURL) -> Option<Target> {
+        if !self.is_site(&atu.did).await {
+            log::debug!("leaving link to {} alone, not the site's repo", atu.did);
+            return None;
+        }
+
+        match (atu.blob, atu.asset) {
+            (true, _) => Some(Target::Blob(atu.key.clone())),
+            (_, true) => match records::asset(&self.site, &format!("/{}", atu.key))?.ok()? {
+                records::Asset::Blob(cid) => Some(Target::Blob(cid)),
+                records::Asset::Chunked(rkey) => Some(Target::Chunked(rkey)),
+            },
+            _ if atu.collection == CHUNKED_COLLECTION => Some(Target::Chunked(atu.key.clone())),
+            _ => Some(Target::Page {
+                collection: atu.collection.clone(),
+                rkey: atu.key.clone(),
+            }),
+        }
+    }
+
+    /// is_site returns true if id, a handle or a DID, points to the site's repo.
+    async fn is_site(&mut self, id: &str) -> bool {
+        let key = id.to_lowercase();
+
+        if let Some(is) = self.ids.get(&key) {
+            return *is;
+        }
+
+        let is = match id.starts_with("did:") {
+            true => false,
+            false => match identity::solve(&self.host, id).await {
+                Ok(i) => i.did == self.did,
+                Err(e) => {
+                    log::debug!("cannot resolve {}: {}", id, e);
+                    false
+                }
+            },
+        };
+
+        self.ids.insert(key, is);
+
+        is
+    }
+
+    /// page_path returns the path the page target is written to: the one it was published from
+    /// if it's in the site's path index, one made of its collection and key otherwise.
+    fn page_path(&mut self, target: &Target) -> String {
+        if let Some(p) = self.paths.get(target) {
+            return p.clone();
+        }
+
+        let path = match target {
+            Target::Page { collection, rkey } => {
+                format!("{}/{}/{}.html", PAGES_DIR, collection, rkey)
+            }
+            _ => unreachable!("only pages have a path known before they're fetched"),
+        };
+
+        self.paths.insert(target.clone(), path.clone());
+
+        path
+    }
+
+    /// file_path returns the path the blob or chunked file target, named name, is written to:
+    /// the one it was published from if it's a standalone asset, one made of its name and
+    /// mime_type otherwise.
+    fn file_path(&mut self, target: &Target, name: &str, mime_type: &str) -> String {
+        if let Some(p) = self.paths.get(target) {
+            return p.clone();
+        }
+
+        let path = format!("{}/{}{}", BLOBS_DIR, name, links::extension(mime_type));
+
+        self.paths.insert(target.clone(), path.clone());
+
+        path
+    }
+
+    async fn record(&self, collection: String, rkey: String) -> Result<Value> {
+        Ok(atproto::fetch_record(
+            &self.host,
+            self.pds.clone(),
+            self.did.clone(),
+            collection,
+            rkey,
+        )
+        .await
+        .map_err(err)?
+        .value)
+    }
+
+    /// blob returns the content of the blob cid, along with its MIME type.
+    async fn blob(&self, cid: String) -> Result<(Vec<u8>, String)> {
+        let stored = records::stored_mime_type(&self.site, &cid);
+
+        let resp = atproto::get_blob(&self.host, self.pds.clone(), self.did.clone(), cid)
+            .await
+            .map_err(err)?;
+
+        let served = resp.header("Content-Type").map(|m| m.to_string());
+        let mime_type = sniff::or_sniff(stored, &resp.body, served);
+
+        Ok((resp.body, mime_type))
+    }
+
+    fn write(&self, path: &str, data: &[u8]) -> Result<()> {
+        let (dest, mut out) = self.create(path)?;
+
+        out.write_all(data)
+            .with_context(|| format!("cannot write {}", dest.display()))?;
+
+        log::info!("Exported {}", path);
+
+        Ok(())
+    }
+
+    /// create creates the file at path in the export, along with its directories.
+    fn create(&self, path: &str) -> Result<(PathBuf, File)> {
+        // paths come from records, which must not write outside of the export
+        if path
+            .split('/')
+            .any(|c| c.is_empty() || c == "." || c == ".." || c.contains('\\'))
+        {
+            return Err(anyhow!("refusing to write to {}", path));
+        }
+
+        let dest = self.out.join(path);
+
+        if let Some(dir) = dest.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("cannot create {}", dir.display()))?;
+        }
+
+        let file =
+            File::create(&dest).with_context(|| format!("cannot write {}", dest.display()))?;
+
+        Ok((dest, file))
+    }
+}
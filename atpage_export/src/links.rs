@@ -0,0 +1,228 @@
+use lol_html::{element, errors::RewritingError, rewrite_str, RewriteStrSettings};
+use std::collections::HashMap;
+
+/// Attributes holding the links pages point to other pages and files with.
+const LINK_ATTRS: [&str; 4] = ["href", "src", "poster", "data"];
+
+/// Attribute holding a comma-separated list of image candidates, each a link and a descriptor.
+const SRCSET: &str = "srcset";
+
+/// collect returns every link in the HTML page content.
+pub fn collect(content: &str) -> Result<Vec<String>, RewritingError> {
+    let links = std::cell::RefCell::new(vec![]);
+
+    let handlers = LINK_ATTRS
+        .iter()
+        .map(|attr| {
+            element!(format!("[{}]", attr), |el| {
+                if let Some(link) = el.get_attribute(attr) {
+                    links.borrow_mut().push(link);
+                }
+                Ok(())
+            })
+        })
+        .chain(std::iter::once(element!(format!("[{}]", SRCSET), |el| {
+            let srcset = el.get_attribute(SRCSET).unwrap_or_default();
+            links.borrow_mut().extend(
+                candidates(&srcset)
+                    .map(|(_, link, _)| link.to_string())
+                    .filter(|link| !link.is_empty()),
+            );
+            Ok(())
+        })))
+        .collect::<Vec<_>>();
+
+    rewrite_str(
+        content,
+        RewriteStrSettings {
+            element_content_handlers: handlers,
+            ..RewriteStrSettings::new()
+        },
+    )?;
+
+    Ok(links.into_inner())
+}
+
+/// rewrite replaces every link in the HTML page content found in replacements.
+pub fn rewrite(
+    content: &str,
+    replacements: &HashMap<String, String>,
+) -> Result<String, RewritingError> {
+    let handlers = LINK_ATTRS
+        .iter()
+        .map(|attr| {
+            element!(format!("[{}]", attr), |el| {
+                let link = el.get_attribute(attr).unwrap_or_default();
+
+                if let Some(new) = replacements.get(&link) {
+                    el.set_attribute(attr, new)?;
+                }
+                Ok(())
+            })
+        })
+        .chain(std::iter::once(element!(format!("[{}]", SRCSET), |el| {
+            let srcset = el.get_attribute(SRCSET).unwrap_or_default();
+
+            let rewritten = candidates(&srcset)
+                .map(|(space, link, descriptor)| {
+                    let link = replacements.get(link).map(|l| l.as_str()).unwrap_or(link);
+                    format!("{}{}{}", space, link, descriptor)
+                })
+                .collect::<Vec<String>>()
+                .join(",");
+
+            el.set_attribute(SRCSET, &rewritten)?;
+            Ok(())
+        })))
+        .collect::<Vec<_>>();
+
+    rewrite_str(
+        content,
+        RewriteStrSettings {
+            element_content_handlers: handlers,
+            ..RewriteStrSettings::new()
+        },
+    )
+}
+
+/// candidates splits srcset in its image candidates, each one returned as the whitespace before
+/// its link, the link and the descriptor that follows, so that joining them back with commas
+/// gives srcset again.
+fn candidates(srcset: &str) -> impl Iterator<Item = (&str, &str, &str)> {
+    srcset.split(',').map(|candidate| {
+        let link_start = candidate.len() - candidate.trim_start().len();
+        let (space, rest) = candidate.split_at(link_start);
+        let link_end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let (link, descriptor) = rest.split_at(link_end);
+
+        (space, link, descriptor)
+    })
+}
+
+/// split splits link in the path it points to and the query string and fragment that follow.
+pub fn split(link: &str) -> (&str, &str) {
+    match link.find(['?', '#']) {
+        Some(i) => link.split_at(i),
+        None => (link, ""),
+    }
+}
+
+/// relative returns the link to the file at path to, from a page at path from.
+/// Both paths are relative to the root of the export.
+pub fn relative(from: &str, to: &str) -> String {
+    let from = from.split('/').collect::<Vec<&str>>();
+    let to = to.split('/').collect::<Vec<&str>>();
+
+    // the last component of from is the page itself
+    let from_dir = &from[..from.len() - 1];
+
+    let common = from_dir
+        .iter()
+        .zip(to.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    // the file itself can't be part of the common prefix
+    let common = common.min(to.len() - 1);
+
+    let mut parts = vec![".."; from_dir.len() - common];
+    parts.extend(&to[common..]);
+
+    parts.join("/")
+}
+
+/// extension returns the file extension files of mime_type are usually named with, dot included,
+/// so that web servers guess their type back.
+pub fn extension(mime_type: &str) -> &'static str {
+    let mime_type = mime_type.split(';').next().unwrap_or_default().trim();
+
+    match mime_type {
+        "text/html" => ".html",
+        "text/css" => ".css",
+        "text/javascript" | "application/javascript" => ".js",
+        "text/plain" => ".txt",
+        "text/xml" | "application/xml" => ".xml",
+        "application/json" => ".json",
+        "application/pdf" => ".pdf",
+        "application/wasm" => ".wasm",
+        "application/zip" => ".zip",
+        "application/gzip" => ".gz",
+        "image/png" => ".png",
+        "image/jpeg" => ".jpg",
+        "image/gif" => ".gif",
+        "image/webp" => ".webp",
+        "image/avif" => ".avif",
+        "image/svg+xml" => ".svg",
+        "image/x-icon" | "image/vnd.microsoft.icon" => ".ico",
+        "font/woff" => ".woff",
+        "font/woff2" => ".woff2",
+        "font/ttf" => ".ttf",
+        "font/otf" => ".otf",
+        "audio/mpeg" => ".mp3",
+        "audio/ogg" => ".ogg",
+        "audio/wav" | "audio/x-wav" => ".wav",
+        "audio/flac" => ".flac",
+        "video/mp4" => ".mp4",
+        "video/webm" => ".webm",
+        _ => "",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_within_a_directory() {
+        assert_eq!(relative("blog/post.html", "blog/other.html"), "other.html");
+    }
+
+    #[test]
+    fn links_from_the_root() {
+        assert_eq!(relative("index.html", "blog/post.html"), "blog/post.html");
+    }
+
+    #[test]
+    fn links_up_to_the_root() {
+        assert_eq!(
+            relative("blog/2024/post.html", "style.css"),
+            "../../style.css"
+        );
+    }
+
+    #[test]
+    fn links_across_directories() {
+        assert_eq!(
+            relative("blog/post.html", "at/blobs/bafkrei.png"),
+            "../at/blobs/bafkrei.png"
+        );
+    }
+
+    #[test]
+    fn links_to_files_named_like_directories() {
+        assert_eq!(relative("blog/post.html", "blog"), "../blog");
+    }
+
+    #[test]
+    fn splits_queries_and_fragments() {
+        assert_eq!(split("/post.html?a=b#top"), ("/post.html", "?a=b#top"));
+        assert_eq!(split("/post.html"), ("/post.html", ""));
+    }
+
+    #[test]
+    fn collects_srcset_candidates() {
+        let links = collect(r#"<img src="/a.png" srcset="/a.png 1x, /b.png 2x">"#).unwrap();
+
+        assert_eq!(links, vec!["/a.png", "/a.png", "/b.png"]);
+    }
+
+    #[test]
+    fn rewrites_srcset_candidates() {
+        let replacements = HashMap::from([("/b.png".to_string(), "b.png".to_string())]);
+
+        assert_eq!(
+            rewrite(r#"<img srcset="/a.png 1x, /b.png 2x">"#, &replacements).unwrap(),
+            r#"<img srcset="/a.png 1x, b.png 2x">"#
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use anyhow::{anyhow, Result};
+use atpage_renderer::{config::Config, host::Native};
+use clap::Parser;
+use std::path::PathBuf;
+
+mod export;
+mod links;
+
+const DEFAULT_OUT: &str = "export";
+
+#[derive(Parser, Debug)]
+#[command(version, about)]
+/// Exports an atpage site into a directory of plain HTML files and assets, servable by any web
+/// server.
+struct Args {
+    /// AT URI of the site, like at://alice.bsky.social, or of one of its pages.
+    at_uri: String,
+
+    /// Directory to write the site to.
+    #[arg(long, default_value = DEFAULT_OUT)]
+    out: PathBuf,
+
+    /// Only export records proven to come from their repo.
+    #[arg(long)]
+    verify: bool,
+
+    /// DNS-over-HTTPS endpoint speaking the JSON API, used to look up handles' _atproto TXT records.
+    #[arg(long)]
+    doh_resolver: Option<String>,
+
+    /// XRPC service whose resolveHandle is used when neither DNS nor HTTPS resolve a handle.
+    #[arg(long)]
+    handle_resolver: Option<String>,
+
+    /// PLC directory did:plc documents are fetched from.
+    #[arg(long)]
+    plc_directory: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    setup_log();
+
+    let args = Args::parse();
+
+    if !args.at_uri.starts_with("at://") {
+        return Err(anyhow!("{} isn't an AT URI", args.at_uri));
+    }
+
+    let defaults = Config::default();
+    let config = Config {
+        verify: args.verify,
+        doh_resolver: args.doh_resolver.unwrap_or(defaults.doh_resolver.clone()),
+        handle_resolver: args.handle_resolver,
+        plc_directory: args.plc_directory.unwrap_or(defaults.plc_directory.clone()),
+        ..defaults
+    };
+
+    let stats = export::Export::new(Native::new(config), &args.at_uri, args.out.clone())
+        .await?
+        .run()
+        .await?;
+
+    log::info!(
+        "Exported {} pages and {} files to {}",
+        stats.pages,
+        stats.files,
+        args.out.display()
+    );
+
+    Ok(())
+}
+
+fn setup_log() {
+    if std::env::var("RUST_LOG").is_err() {
+        std::env::set_var("RUST_LOG", "info")
+    }
+
+    env_logger::init();
+}
@@ -0,0 +1,174 @@
+use atpage_renderer::{atproto::Record, host::Blob, identity::Identity};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Records keeps records for a fixed amount of time, since they can change under the same key.
+pub struct Records {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Record, Instant)>>,
+}
+
+impl Records {
+    pub fn new(ttl: Duration) -> Self {
+        Records {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<Record> {
+        let mut entries = self.entries.lock().unwrap();
+
+        match entries.get(key) {
+            Some((record, fetched)) if fetched.elapsed() < self.ttl => Some(record.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn put(&self, key: String, record: Record) {
+        let mut entries = self.entries.lock().unwrap();
+
+        // expired entries are only dropped when looked up, sweep them once in a while
+        if entries.len() > 10_000 {
+            entries.retain(|_, (_, fetched)| fetched.elapsed() < self.ttl);
+        }
+
+        entries.insert(key, (record, Instant::now()));
+    }
+}
+
+/// Blobs keeps blobs up to a total size, evicting the least recently used first.
+/// Blobs are content-addressed, so they never go stale.
+pub struct Blobs {
+    max_size: u64,
+    inner: Mutex<BlobsInner>,
+}
+
+#[derive(Default)]
+struct BlobsInner {
+    entries: HashMap<String, Blob>,
+    /// Keys from the least to the most recently used.
+    order: VecDeque<String>,
+    size: u64,
+}
+
+impl Blobs {
+    pub fn new(max_size: u64) -> Self {
+        Blobs {
+            max_size,
+            inner: Mutex::new(BlobsInner::default()),
+        }
+    }
+
+    pub fn get(&self, cid: &str) -> Option<Blob> {
+        let mut inner = self.inner.lock().unwrap();
+
+        let blob = inner.entries.get(cid).cloned()?;
+
+        inner.order.retain(|k| k != cid);
+        inner.order.push_back(cid.to_string());
+
+        Some(blob)
+    }
+
+    /// fits returns true if a blob of size bytes can be kept.
+    pub fn fits(&self, size: u64) -> bool {
+        size <= self.max_size
+    }
+
+    pub fn put(&self, cid: String, blob: Blob) {
+        let size = blob.data.len() as u64;
+        if !self.fits(size) {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+
+        if inner.entries.contains_key(&cid) {
+            return;
+        }
+
+        while inner.size + size > self.max_size {
+            let evicted = match inner.order.pop_front() {
+                Some(k) => k,
+                None => break,
+            };
+
+            if let Some(b) = inner.entries.remove(&evicted) {
+                inner.size -= b.data.len() as u64;
+            }
+        }
+
+        inner.size += size;
+        inner.order.push_back(cid.clone());
+        inner.entries.insert(cid, blob);
+    }
+}
+
+/// Identities keeps what names, handles, DIDs or domains, resolve to, or that they don't, for a
+/// fixed amount of time and up to a number of names, evicting the least recently used first: any
+/// request can make the gateway look up a new name.
+pub struct Identities {
+    ttl: Duration,
+    max_entries: usize,
+    inner: Mutex<IdentitiesInner>,
+}
+
+#[derive(Default)]
+struct IdentitiesInner {
+    entries: HashMap<String, (Option<Identity>, Instant)>,
+    /// Names from the least to the most recently used.
+    order: VecDeque<String>,
+}
+
+impl Identities {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Identities {
+            ttl,
+            max_entries,
+            inner: Mutex::new(IdentitiesInner::default()),
+        }
+    }
+
+    /// get returns what name resolved to, None if it wasn't or it expired.
+    pub fn get(&self, name: &str) -> Option<Option<Identity>> {
+        let mut inner = self.inner.lock().unwrap();
+
+        let (id, resolved) = inner.entries.get(name).cloned()?;
+        inner.order.retain(|k| k != name);
+
+        if resolved.elapsed() >= self.ttl {
+            inner.entries.remove(name);
+            return None;
+        }
+
+        inner.order.push_back(name.to_string());
+
+        Some(id)
+    }
+
+    pub fn put(&self, name: String, id: Option<Identity>) {
+        let mut inner = self.inner.lock().unwrap();
+
+        if inner.entries.remove(&name).is_some() {
+            inner.order.retain(|k| *k != name);
+        }
+
+        while inner.entries.len() >= self.max_entries {
+            match inner.order.pop_front() {
+                Some(evicted) => inner.entries.remove(&evicted),
+                None => break,
+            };
+        }
+
+        inner.order.push_back(name.clone());
+        inner.entries.insert(name, (id, Instant::now()));
+    }
+}
@@ -0,0 +1,121 @@
+use anyhow::{anyhow, Context, Result};
+use atpage_gateway::serve;
+use atpage_renderer::{config::Config, host::Native};
+use clap::Parser;
+use std::{collections::HashMap, sync::Arc};
+
+const DEFAULT_LISTEN: &str = "127.0.0.1:8080";
+const DEFAULT_SCHEME: &str = "https";
+const RECORD_TTL: u64 = 60;
+
+#[derive(Parser, Debug)]
+#[command(version, about)]
+/// Serves atpage sites over plain HTTP, rendering pages on the server instead of in a service worker.
+struct Args {
+    /// Address to listen on.
+    #[arg(long, env = "ATPAGE_LISTEN", default_value = DEFAULT_LISTEN)]
+    listen: String,
+
+    /// Scheme clients reach the gateway through, set it to "http" when no reverse proxy terminates
+    /// TLS in front of it. Security headers name origins made of it and the Host header.
+    #[arg(long, default_value = DEFAULT_SCHEME)]
+    scheme: String,
+
+    /// Domain serving the site of a handle or DID at plain paths, as "domain=handle", can be repeated.
+    /// Every domain serves /at/ URLs.
+    #[arg(long)]
+    site: Vec<String>,
+
+    /// Serve domains that aren't given with --site with the site claiming them, through an
+    /// _atpage TXT record or /.well-known/atpage.json.
+    #[arg(long)]
+    domains: bool,
+
+    /// Handle or DID whose pages may run scripts, can be repeated.
+    /// Pages of any other repo are sanitized, sites are always trusted on their own domain.
+    #[arg(long)]
+    trusted: Vec<String>,
+
+    /// Only serve records proven to come from their repo.
+    #[arg(long)]
+    verify: bool,
+
+    /// DNS-over-HTTPS endpoint speaking the JSON API, used to look up handles' _atproto TXT records.
+    #[arg(long)]
+    doh_resolver: Option<String>,
+
+    /// XRPC service whose resolveHandle is used when neither DNS nor HTTPS resolve a handle.
+    #[arg(long)]
+    handle_resolver: Option<String>,
+
+    /// PLC directory did:plc documents are fetched from.
+    #[arg(long)]
+    plc_directory: Option<String>,
+
+    /// How long resolved handles and DIDs are cached for, in seconds.
+    #[arg(long)]
+    identity_ttl: Option<u64>,
+
+    /// How long records are cached for, in seconds.
+    #[arg(long, default_value_t = RECORD_TTL)]
+    record_ttl: u64,
+
+    /// Maximum size of the blobs kept in memory, in bytes.
+    #[arg(long)]
+    cache_size: Option<u64>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    setup_log();
+
+    let args = Args::parse();
+
+    let sites = args
+        .site
+        .iter()
+        .map(|s| {
+            s.split_once('=')
+                .map(|(domain, id)| (domain.to_lowercase(), id.to_string()))
+                .ok_or(anyhow!("malformed site {s}, must be \"domain=handle\""))
+        })
+        .collect::<Result<HashMap<String, String>>>()?;
+
+    let defaults = Config::default();
+    let config = Config {
+        site: None,
+        domains: args.domains,
+        trusted: args.trusted,
+        verify: args.verify,
+        doh_resolver: args.doh_resolver.unwrap_or(defaults.doh_resolver),
+        handle_resolver: args.handle_resolver,
+        plc_directory: args.plc_directory.unwrap_or(defaults.plc_directory),
+        identity_ttl: args.identity_ttl.unwrap_or(defaults.identity_ttl),
+        cache_size: args.cache_size.unwrap_or(defaults.cache_size),
+    };
+
+    let gateway = Arc::new(serve::Gateway::new(
+        Native::new(config),
+        args.scheme,
+        sites,
+        std::time::Duration::from_secs(args.record_ttl),
+    ));
+
+    let listener = tokio::net::TcpListener::bind(&args.listen)
+        .await
+        .with_context(|| format!("cannot listen on {}", args.listen))?;
+
+    log::info!("Serving atpage sites on {}", args.listen);
+
+    axum::serve(listener, serve::router(gateway)).await?;
+
+    Ok(())
+}
+
+fn setup_log() {
+    if std::env::var("RUST_LOG").is_err() {
+        std::env::set_var("RUST_LOG", "info")
+    }
+
+    env_logger::init();
+}
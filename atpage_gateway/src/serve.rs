@@ -0,0 +1,308 @@
+use crate::cache;
+use atpage_renderer::{
+    atproto::{self, Error},
+    conditional::Conditions,
+    config::Config,
+    domain,
+    host::{self, Blob, Host, Native, Partial, Streamed},
+    identity::{self, Identity},
+    range::Range,
+    serve::{self, Site},
+};
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Router,
+};
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+/// Number of handles and DIDs whose resolution is remembered.
+const MAX_IDENTITIES: usize = 10_000;
+/// Number of domains whose lookup is remembered.
+const MAX_DOMAINS: usize = 10_000;
+
+/// Gateway resolves and serves atpage sites, keeping what it fetches in memory.
+pub struct Gateway {
+    host: Native,
+    /// Scheme clients reach the gateway through.
+    scheme: String,
+    /// Handle or DID of the site served at plain paths of each domain.
+    sites: HashMap<String, String>,
+    /// Identities of handles and DIDs, None if they don't resolve.
+    identities: cache::Identities,
+    /// Repos claiming domains that aren't in sites, None if nobody does.
+    domains: cache::Identities,
+    records: cache::Records,
+    blobs: cache::Blobs,
+}
+
+pub fn router(gateway: Arc<Gateway>) -> Router {
+    Router::new().fallback(handle).with_state(gateway)
+}
+
+async fn handle(State(gw): State<Arc<Gateway>>, req: Request) -> Response {
+    let headers = req.headers();
+    let header = |name| headers.get(name).and_then(|h| h.to_str().ok());
+
+    let host = header(header::HOST).unwrap_or_default().to_lowercase();
+    if !valid_host(&host) {
+        return (StatusCode::BAD_REQUEST, "malformed Host header").into_response();
+    }
+
+    let domain = host.split_once(':').map(|(d, _)| d).unwrap_or(&host);
+
+    let path = percent_encoding::percent_decode_str(req.uri().path())
+        .decode_utf8_lossy()
+        .to_string();
+
+    log::debug!("serving {}{}", host, path);
+
+    // domains are only looked up to serve their own site at plain paths, so that /at/ URLs can't
+    // be used to make the gateway look up whatever name a Host header holds
+    let lookup = !path.starts_with("/at/") && !host.contains(':');
+
+    let req = serve::Request {
+        origin: format!("{}://{}", gw.scheme, host),
+        site: gw.site_at(domain, lookup),
+        path,
+        conditions: Conditions::new(header(header::RANGE), header(header::IF_NONE_MATCH)),
+    };
+
+    response(gw.clone(), serve::route(&*gw, req).await)
+}
+
+/// valid_host returns true if host is a host name with an optional port, and nothing else: the
+/// origin in security headers is made from it.
+fn valid_host(host: &str) -> bool {
+    let (name, port) = match host.split_once(':') {
+        Some((name, port)) => (name, Some(port)),
+        None => (host, None),
+    };
+
+    let name_ok = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-');
+    let port_ok = port.is_none_or(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit()));
+
+    name_ok && port_ok
+}
+
+/// response turns resp into an HTTP response, reading blobs and chunked files as the body is
+/// sent.
+fn response(gw: Arc<Gateway>, resp: serve::Response<<Gateway as Host>::BlobStream>) -> Response {
+    let body = match resp.body {
+        serve::Body::Empty => Body::empty(),
+        serve::Body::Bytes(data) => Body::from(data),
+        serve::Body::Stream(content) => {
+            Body::from_stream(content.map_err(|e| std::io::Error::other(e.to_string())))
+        }
+        serve::Body::Chunks { pds, did, parts } => Body::from_stream(
+            serve::chunks(gw, pds, did, parts).map_err(|e| std::io::Error::other(e.to_string())),
+        ),
+    };
+
+    let mut builder = Response::builder().status(resp.status);
+    for (name, value) in resp.headers {
+        builder = builder.header(name, value);
+    }
+
+    match builder.body(body) {
+        Ok(r) => r,
+        Err(e) => {
+            log::error!("can't build response: {}", e);
+
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+impl Gateway {
+    pub fn new(
+        host: Native,
+        scheme: String,
+        sites: HashMap<String, String>,
+        record_ttl: Duration,
+    ) -> Self {
+        let cache_size = host.config().cache_size;
+        let identity_ttl = Duration::from_secs(host.config().identity_ttl);
+
+        Gateway {
+            host,
+            scheme,
+            sites,
+            identities: cache::Identities::new(identity_ttl, MAX_IDENTITIES),
+            domains: cache::Identities::new(identity_ttl, MAX_DOMAINS),
+            records: cache::Records::new(record_ttl),
+            blobs: cache::Blobs::new(cache_size),
+        }
+    }
+
+    /// site_at returns the site served at plain paths of domain, if any: the one given for it, or
+    /// the one claiming it when looking sites up by domain and lookup is set.
+    fn site_at(&self, domain: &str, lookup: bool) -> Option<Site> {
+        match self.sites.get(domain) {
+            Some(id) => Some(Site::Repo(id.clone())),
+            None if lookup && self.host.config().domains => Some(Site::Domain(domain.to_string())),
+            None => None,
+        }
+    }
+}
+
+impl Host for Gateway {
+    type BlobStream = BoxStream<'static, Result<Vec<u8>, Error>>;
+
+    async fn fetch(&self, url: &str, headers: &[(&str, &str)]) -> Result<host::Response, Error> {
+        self.host.fetch(url, headers).await
+    }
+
+    fn config(&self) -> &Config {
+        self.host.config()
+    }
+
+    fn now(&self) -> f64 {
+        self.host.now()
+    }
+
+    fn web_url(&self, domain: &str, path: &str) -> String {
+        self.host.web_url(domain, path)
+    }
+
+    /// identity resolves id, a handle or a DID, caching it for the configured time.
+    /// Ids that don't resolve are remembered too, while failures to reach the services resolving
+    /// them aren't.
+    async fn identity(&self, id: &str) -> Result<Identity, Error> {
+        // handles are case-insensitive
+        let key = id.to_lowercase();
+
+        if let Some(i) = self.identities.get(&key) {
+            return i.ok_or(Error::NoDIDFound(key));
+        }
+
+        let (res, entry) = match identity::solve(&self.host, id).await {
+            Ok(i) => (Ok(i.clone()), Some(i)),
+            Err(
+                e @ (Error::NoDIDFound(_)
+                | Error::NoPDSFound(_)
+                | Error::UnsupportedDID(_)
+                | Error::HandleMismatch(_)),
+            ) => (Err(e), None),
+            Err(e) => return Err(e),
+        };
+
+        self.identities.put(key, entry);
+
+        res
+    }
+
+    /// domain resolves the repo claiming domain, caching it for the configured time.
+    /// Domains nobody claims are remembered too, as every request to them would look them up
+    /// again otherwise.
+    async fn domain(&self, domain: &str) -> Result<Identity, Error> {
+        if let Some(id) = self.domains.get(domain) {
+            return id.ok_or(Error::UnclaimedDomain(domain.to_string()));
+        }
+
+        let (res, entry) = match domain::solve(&self.host, domain).await {
+            Ok(id) => (Ok(id.clone()), Some(id)),
+            Err(Error::UnclaimedDomain(d)) => (Err(Error::UnclaimedDomain(d)), None),
+            Err(e) => return Err(e),
+        };
+
+        self.domains.put(domain.to_string(), entry);
+
+        res
+    }
+
+    async fn record(
+        &self,
+        pds: String,
+        did: String,
+        collection: String,
+        rkey: String,
+    ) -> Result<serde_json::Value, Error> {
+        let key = format!("{}/{}/{}", did, collection, rkey);
+
+        if let Some(r) = self.records.get(&key) {
+            return Ok(r.value);
+        }
+
+        let record = atproto::fetch_record(&self.host, pds, did, collection, rkey).await?;
+
+        self.records.put(key, record.clone());
+
+        Ok(record.value)
+    }
+
+    async fn blob(&self, pds: String, did: String, cid: String) -> Result<Blob, Error> {
+        if let Some(b) = self.blobs.get(&cid) {
+            return Ok(b);
+        }
+
+        let resp = atproto::get_blob(&self.host, pds, did, cid.clone()).await?;
+
+        let blob = Blob {
+            mime_type: resp.header("Content-Type").map(|m| m.to_string()),
+            data: resp.body,
+        };
+
+        self.blobs.put(cid, blob.clone());
+
+        Ok(blob)
+    }
+
+    /// blob_stream serves blobs kept in memory from there, and streams the others from the PDS.
+    /// Blobs small enough to be kept are read whole first, to keep them.
+    async fn blob_stream(
+        &self,
+        pds: String,
+        did: String,
+        cid: String,
+    ) -> Result<Streamed<Self::BlobStream>, Error> {
+        if let Some(b) = self.blobs.get(&cid) {
+            return Ok(whole(b));
+        }
+
+        let streamed = self.host.blob_stream(pds, did, cid.clone()).await?;
+
+        match streamed.size {
+            Some(size) if self.blobs.fits(size) => {
+                let blob = Blob {
+                    data: streamed.body.try_concat().await?,
+                    mime_type: streamed.mime_type,
+                };
+
+                self.blobs.put(cid, blob.clone());
+
+                Ok(whole(blob))
+            }
+            _ => Ok(streamed),
+        }
+    }
+
+    async fn cached_blob(&self, cid: &str) -> Option<Blob> {
+        self.blobs.get(cid)
+    }
+
+    async fn blob_range(
+        &self,
+        pds: String,
+        did: String,
+        cid: String,
+        range: Range,
+    ) -> Result<Option<Partial<Self::BlobStream>>, Error> {
+        self.host.blob_range(pds, did, cid, range).await
+    }
+}
+
+/// whole returns blob as a stream of a single chunk.
+fn whole(blob: Blob) -> Streamed<BoxStream<'static, Result<Vec<u8>, Error>>> {
+    Streamed {
+        size: Some(blob.data.len() as u64),
+        mime_type: blob.mime_type,
+        body: futures::stream::once(async { Ok(blob.data) }).boxed(),
+    }
+}
@@ -0,0 +1,243 @@
+#[path = "../../atpage_renderer/tests/common/mod.rs"]
+mod common;
+
+use atpage_gateway::serve::{self, Gateway};
+use atpage_renderer::config::Config;
+use axum::{
+    body::{self, Body},
+    http::Request,
+    response::Response,
+};
+use common::{blob_ref, cid, Services, DID, HANDLE};
+use serde_json::json;
+use shared::atproto::{PAGE_COLLECTION, SITE_COLLECTION, SITE_RKEY};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tower::ServiceExt;
+
+const PAGE: &[u8] =
+    b"<!DOCTYPE html><html><body><h1>Hello</h1><script>alert(1)</script></body></html>";
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+/// gateway publishes a site with a page and a PNG on s, and returns a gateway serving it at
+/// example.com, and other sites at the domains they claim if domains is set.
+async fn gateway(s: &Services, domains: bool) -> Arc<Gateway> {
+    s.did(DID, HANDLE).await;
+    s.handle(HANDLE, &[DID]).await;
+    s.not_found().await;
+
+    s.record(
+        DID,
+        PAGE_COLLECTION,
+        "index",
+        json!({ "title": "Hello", "content": blob_ref(PAGE, "text/html") }),
+    )
+    .await;
+    s.blob(DID, &cid(PAGE), PAGE).await;
+    s.blob(DID, &cid(PNG), PNG).await;
+
+    s.record(
+        DID,
+        SITE_COLLECTION,
+        SITE_RKEY,
+        json!({
+            "assets": [{ "path": "/logo", "blob": blob_ref(PNG, "image/png") }],
+            "pages": [{ "path": "/index.html", "rkey": "index" }],
+        }),
+    )
+    .await;
+
+    let config = Config {
+        domains,
+        ..Default::default()
+    };
+    let sites = HashMap::from([("example.com".to_string(), HANDLE.to_string())]);
+
+    Arc::new(Gateway::new(
+        s.host_with(config),
+        "https".to_string(),
+        sites,
+        Duration::from_secs(60),
+    ))
+}
+
+async fn get(gw: Arc<Gateway>, host: &str, path: &str, headers: &[(&str, &str)]) -> Response {
+    let mut req = Request::builder().uri(path).header("Host", host);
+    for (name, value) in headers {
+        req = req.header(*name, *value);
+    }
+
+    serve::router(gw)
+        .oneshot(req.body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+}
+
+async fn text(resp: Response) -> String {
+    let body = body::to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+
+    String::from_utf8_lossy(&body).to_string()
+}
+
+#[tokio::test]
+async fn serves_sites_at_their_domain() {
+    let s = Services::start().await;
+    let gw = gateway(&s, false).await;
+
+    let resp = get(gw.clone(), "example.com", "/", &[]).await;
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.headers()["Content-Type"], "text/html; charset=utf-8");
+    assert!(resp.headers()["Content-Security-Policy"]
+        .to_str()
+        .unwrap()
+        .contains(&format!("https://example.com/at/{}/", DID)));
+
+    // the site is trusted on its own domain
+    assert!(text(resp).await.contains("<script>"));
+
+    let resp = get(gw, "example.com", "/nothing.html", &[]).await;
+    assert_eq!(resp.status(), 404);
+}
+
+#[tokio::test]
+async fn refuses_malformed_hosts() {
+    let s = Services::start().await;
+    let gw = gateway(&s, false).await;
+
+    let resp = get(gw, "evil.com/x 'unsafe-eval'", "/", &[]).await;
+    assert_eq!(resp.status(), 400);
+}
+
+#[tokio::test]
+async fn sanitizes_other_repos() {
+    let s = Services::start().await;
+    let gw = gateway(&s, false).await;
+
+    let resp = get(
+        gw,
+        "elsewhere.com",
+        &format!("/at/{}/{}/index", DID, PAGE_COLLECTION),
+        &[],
+    )
+    .await;
+    assert_eq!(resp.status(), 200);
+    assert!(resp.headers()["Content-Security-Policy"]
+        .to_str()
+        .unwrap()
+        .contains("script-src 'none'"));
+    assert!(!text(resp).await.contains("<script>"));
+}
+
+#[tokio::test]
+async fn serves_ranges_of_assets() {
+    let s = Services::start().await;
+    let gw = gateway(&s, false).await;
+
+    let resp = get(
+        gw.clone(),
+        "example.com",
+        "/logo",
+        &[("Range", "bytes=1-3")],
+    )
+    .await;
+    assert_eq!(resp.status(), 206);
+    assert_eq!(resp.headers()["Content-Type"], "image/png");
+    assert_eq!(
+        resp.headers()["Content-Range"],
+        format!("bytes 1-3/{}", PNG.len())
+    );
+    assert_eq!(text(resp).await, "PNG");
+
+    let etag = format!("\"{}\"", cid(PNG));
+    let resp = get(gw, "example.com", "/logo", &[("If-None-Match", &etag)]).await;
+    assert_eq!(resp.status(), 304);
+}
+
+#[tokio::test]
+async fn only_looks_up_domains_for_their_site() {
+    let s = Services::start().await;
+    let gw = gateway(&s, true).await;
+
+    let resp = get(
+        gw.clone(),
+        "internal.example",
+        &format!("/at/{}/{}/index", DID, PAGE_COLLECTION),
+        &[],
+    )
+    .await;
+    assert_eq!(resp.status(), 200);
+
+    let resp = get(gw, "127.0.0.1:8080", "/", &[]).await;
+    assert_eq!(resp.status(), 404);
+
+    let lookups = s
+        .server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|r| r.url.query().unwrap_or_default().contains("_atpage"))
+        .count();
+    assert_eq!(lookups, 0);
+}
+
+#[tokio::test]
+async fn remembers_repos_that_dont_resolve() {
+    let s = Services::start().await;
+    let gw = gateway(&s, false).await;
+
+    let path = format!("/at/did:plc:nobody/{}/index", PAGE_COLLECTION);
+    for _ in 0..2 {
+        let resp = get(gw.clone(), "example.com", &path, &[]).await;
+        assert_eq!(resp.status(), 404);
+    }
+
+    let lookups = s
+        .server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|r| r.url.path().contains("did:plc:nobody"))
+        .count();
+    assert_eq!(lookups, 1);
+}
+
+#[tokio::test]
+async fn never_resolves_internal_hosts() {
+    let s = Services::start().await;
+    let gw = gateway(&s, false).await;
+
+    for repo in ["localhost", "metadata.internal", "did:web:127.0.0.1"] {
+        let path = format!("/at/{}/{}/index", repo, PAGE_COLLECTION);
+
+        let resp = get(gw.clone(), "example.com", &path, &[]).await;
+        assert!(resp.status().is_client_error(), "{}", repo);
+    }
+
+    let requests = s.server.received_requests().await.unwrap();
+    assert!(requests.is_empty(), "{:?}", requests);
+}
+
+#[tokio::test]
+async fn slices_ranges_of_blobs_it_keeps() {
+    let s = Services::start().await;
+    let gw = gateway(&s, false).await;
+
+    let resp = get(gw.clone(), "example.com", "/logo", &[]).await;
+    assert_eq!(resp.status(), 200);
+    text(resp).await;
+
+    let resp = get(gw, "example.com", "/logo", &[("Range", "bytes=1-3")]).await;
+    assert_eq!(resp.status(), 206);
+    assert_eq!(text(resp).await, "PNG");
+
+    let ranges = s
+        .server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|r| r.headers.contains_key("Range"))
+        .count();
+    assert_eq!(ranges, 0);
+}
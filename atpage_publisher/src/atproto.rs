@@ -3,50 +3,74 @@ use atrium_api::{
     agent::atp_agent::{store::MemorySessionStore, AtpAgent},
     com::{
         self,
-        atproto::repo::{create_record, delete_record, list_records},
+        atproto::repo::{create_record, delete_record, list_records, put_record},
     },
     types::{
-        string::{AtIdentifier, RecordKey},
-        BlobRef, Collection,
+        string::{AtIdentifier, Nsid, RecordKey},
+        Blob, BlobRef, CidLink, Collection, TypedBlobRef,
     },
 };
 use atrium_xrpc::XrpcClient;
 use atrium_xrpc_client::reqwest::{ReqwestClient, ReqwestClientBuilder};
-use http::{header::AUTHORIZATION, HeaderMap, HeaderValue};
+use http::{
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    HeaderMap, HeaderValue,
+};
+use sha2::{Digest, Sha256};
 use shared::atproto::ATURL;
 
 use crate::lexicon;
+
+const CID_V1: u8 = 0x01;
+const RAW_CODEC: u8 = 0x55;
+const SHA256_CODE: u8 = 0x12;
+const SHA256_LEN: u8 = 0x20;
+
+/// blob_cid returns the CID a PDS assigns to a blob holding data: a CIDv1 with the raw codec and a
+/// SHA-256 multihash, base32-encoded.
+pub fn blob_cid(data: &[u8]) -> String {
+    let mut cid = vec![CID_V1, RAW_CODEC, SHA256_CODE, SHA256_LEN];
+    cid.extend_from_slice(&Sha256::digest(data));
+
+    multibase::encode(multibase::Base::Base32Lower, cid)
+}
+
+/// mime_type guesses the MIME type of data from its content, or from its file extension.
+fn mime_type(data: &[u8], ext: Option<String>) -> String {
+    match infer::get(data) {
+        Some(mt) => mt.mime_type().to_owned(),
+        None => {
+            let m = mime_guess::from_ext(&ext.unwrap_or_default());
+            m.first_or_text_plain().to_string()
+        }
+    }
+}
+
 pub(crate) struct IdentityData {
     pub did: AtIdentifier,
-    pub handle: AtIdentifier,
     client: ReqwestClient,
     agent: AtpAgent<MemorySessionStore, ReqwestClient>,
+    /// Client for public XRPC queries, which only need their headers read.
+    http: reqwest::Client,
 }
 
 impl IdentityData {
+    /// format_blob_uri returns the /at/ URL of blob, by DID: the renderer only lets pages run
+    /// scripts from their repo's DID paths.
     pub fn format_blob_uri(&self, blob: String) -> String {
-        let did = match self.handle.clone() {
-            AtIdentifier::Did(d) => d.to_string(),
-            AtIdentifier::Handle(h) => h.to_string(),
-        };
-        format!("/at/{}/blobs/{}", did.to_string(), blob)
+        format!("/at/{}/blobs/{}", self.did.as_ref(), blob)
     }
 
-    pub fn format_record_uri(&self, rkey: String) -> String {
-        let did = match self.handle.clone() {
-            AtIdentifier::Did(d) => d.to_string(),
-            AtIdentifier::Handle(h) => h.to_string(),
-        };
-        format!(
-            "/at/{}/{}/{}",
-            did.to_string(),
-            lexicon::COLLECTION_NAME,
-            rkey
-        )
+    pub fn format_chunked_uri(&self, rkey: String) -> String {
+        self.format_collection_uri(lexicon::CHUNKED_COLLECTION_NAME, rkey)
+    }
+
+    fn format_collection_uri(&self, collection: &str, rkey: String) -> String {
+        format!("/at/{}/{}/{}", self.did.as_ref(), collection, rkey)
     }
 
     pub fn did(&self) -> AtIdentifier {
-        return self.did.clone();
+        self.did.clone()
     }
 
     pub async fn upload_page(&self, page_data: lexicon::PageData) -> Result<create_record::Output> {
@@ -55,7 +79,7 @@ impl IdentityData {
         let res = self
             .client
             .send_xrpc::<(), lexicon::InputData, create_record::Output, create_record::Error>(
-                &request,
+                request,
             )
             .await
             .with_context(|| "Can't write webpage to PDS")?;
@@ -71,7 +95,41 @@ impl IdentityData {
         }
     }
 
+    /// put_record creates or overwrites the record described by record_data.
+    pub async fn put_record(
+        &self,
+        record_data: impl Into<lexicon::InputData>,
+    ) -> Result<put_record::Output> {
+        let request = &lexicon::put_record(record_data);
+
+        let res = self
+            .client
+            .send_xrpc::<(), lexicon::InputData, put_record::Output, put_record::Error>(request)
+            .await
+            .with_context(|| "Can't write record to PDS")?;
+
+        match res {
+            atrium_xrpc::OutputDataOrBytes::Data(data) => Ok(data),
+            atrium_xrpc::OutputDataOrBytes::Bytes(_) => {
+                Err(anyhow!("received bytes from put_record call, impossible!"))
+            }
+        }
+    }
+
     pub async fn nuke(&self) -> Result<Vec<String>> {
+        let mut deleted = vec![];
+        for collection in [
+            lexicon::Page::nsid(),
+            lexicon::Site::nsid(),
+            lexicon::Chunked::nsid(),
+        ] {
+            deleted.append(&mut self.nuke_collection(collection).await?);
+        }
+
+        Ok(deleted)
+    }
+
+    async fn nuke_collection(&self, collection: Nsid) -> Result<Vec<String>> {
         let mut deleted = vec![];
         loop {
             let records = self
@@ -82,7 +140,7 @@ impl IdentityData {
                 .repo
                 .list_records(
                     list_records::ParametersData {
-                        collection: lexicon::Page::nsid(),
+                        collection: collection.clone(),
                         cursor: None,
                         limit: None,
                         repo: self.did.clone(),
@@ -106,7 +164,7 @@ impl IdentityData {
                     .repo
                     .delete_record(
                         delete_record::InputData {
-                            collection: lexicon::Page::nsid(),
+                            collection: collection.clone(),
                             repo: self.did.clone(),
                             rkey: RecordKey::new(ru.key.clone()).unwrap(),
                             swap_commit: None,
@@ -123,37 +181,108 @@ impl IdentityData {
         Ok(deleted)
     }
 
+    /// upload_blob uploads data as a blob, unless the PDS has a blob with the same CID already.
     pub async fn upload_blob(
         &self,
         data: Vec<u8>,
         ext: Option<String>,
     ) -> Result<(BlobRef, String)> {
-        let mt = match infer::get(&data) {
-            Some(mt) => Some(mt.mime_type().to_owned()),
-            None => {
-                let m = mime_guess::from_ext(&ext.unwrap_or_default());
-                Some(m.first_or_text_plain().to_string())
-            }
-        };
+        let mt = mime_type(&data, ext);
+
+        self.upload_blob_with_mime(data, Some(mt)).await
+    }
+
+    /// upload_chunked uploads chunks, the pieces data is split in, as blobs, and writes a chunked
+    /// record listing them in order.
+    /// Returns the record key, which is the CID data would have as a single blob.
+    pub async fn upload_chunked(
+        &self,
+        data: &[u8],
+        chunks: Vec<&[u8]>,
+        ext: Option<String>,
+    ) -> Result<String> {
+        let rkey = blob_cid(data);
+
+        let mut blobs = vec![];
+        for chunk in chunks {
+            let (blob, blob_ref) = self
+                .upload_blob_with_mime(chunk.to_vec(), Some("application/octet-stream".to_string()))
+                .await?;
+
+            log::debug!("Uploaded chunk {} of {}", blob_ref, rkey);
+
+            blobs.push(blob);
+        }
+
+        self.put_record(lexicon::ChunkedData {
+            chunked: lexicon::Chunked {
+                mime_type: mime_type(data, ext),
+                size: data.len(),
+                chunks: blobs,
+            },
+            id: self.did(),
+            rkey: rkey.clone(),
+        })
+        .await?;
+
+        Ok(rkey)
+    }
+
+    /// upload_blob_with_mime uploads data as a blob of type mt, unless the PDS has a blob with the
+    /// same CID already.
+    pub async fn upload_blob_with_mime(
+        &self,
+        data: Vec<u8>,
+        mt: Option<String>,
+    ) -> Result<(BlobRef, String)> {
+        let cid = blob_cid(&data);
+        if let Some(blob) = self.existing_blob(&cid, data.len(), mt.clone()).await {
+            log::debug!("blob {} already on the PDS, skipping upload", cid);
+
+            return Ok((blob, cid));
+        }
 
         let res = self.upload_blob_raw(data, mt).await?;
 
-        match res.blob.clone() {
-            atrium_api::types::BlobRef::Typed(t) => {
-                let r = match t {
-                    atrium_api::types::TypedBlobRef::Blob(b) => b,
-                };
-
-                let cid = r.r#ref.0;
-                Ok((
-                    res.blob.clone(),
-                    cid.to_string_of_base(multibase::Base::Base32Lower)?,
-                ))
-            }
-            atrium_api::types::BlobRef::Untyped(u) => Ok((res.blob.clone(), u.cid)),
+        let blob = res.data.blob;
+
+        match blob.clone() {
+            BlobRef::Typed(TypedBlobRef::Blob(b)) => Ok((
+                blob,
+                b.r#ref.0.to_string_of_base(multibase::Base::Base32Lower)?,
+            )),
+            BlobRef::Untyped(u) => Ok((blob, u.cid)),
         }
     }
 
+    /// existing_blob returns a reference to the blob cid of size bytes if the PDS has it already,
+    /// of type mt, or of the type the PDS serves it as if mt is None.
+    /// Only the headers of the blob are read, any failure counts as a missing blob.
+    async fn existing_blob(&self, cid: &str, size: usize, mt: Option<String>) -> Option<BlobRef> {
+        let url = format!(
+            "{}/xrpc/com.atproto.sync.getBlob?did={}&cid={}",
+            self.client.base_uri().trim_end_matches('/'),
+            self.did.as_ref(),
+            cid
+        );
+
+        let resp = self.http.get(url).send().await.ok()?;
+        if !resp.status().is_success() {
+            return None;
+        }
+
+        let mime_type = match mt {
+            Some(mt) => mt,
+            None => resp.headers().get(CONTENT_TYPE)?.to_str().ok()?.to_string(),
+        };
+
+        Some(BlobRef::Typed(TypedBlobRef::Blob(Blob {
+            r#ref: CidLink::try_from(cid).ok()?,
+            mime_type,
+            size,
+        })))
+    }
+
     /// Returns a logged-in ReqwestClient that can be used to perform POST requests.
     pub async fn login(username: String, password: String, pds: String) -> Result<Self> {
         let c = ReqwestClientBuilder::new(pds.clone()).build();
@@ -185,9 +314,9 @@ impl IdentityData {
 
         Ok(IdentityData {
             did: AtIdentifier::Did(session.did.clone()),
-            handle: AtIdentifier::Handle(session.handle.clone()),
             client: c,
             agent,
+            http: reqwest::Client::new(),
         })
     }
 
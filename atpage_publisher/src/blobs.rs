@@ -0,0 +1,138 @@
+use anyhow::{Context, Result};
+use atrium_api::types::BlobRef;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use crate::atproto::{self, IdentityData};
+
+/// Uploaded is a file stored on the PDS.
+#[derive(Clone)]
+pub enum Uploaded {
+    /// A file stored in a single blob, along with its CID.
+    Blob(BlobRef, String),
+
+    /// A file too big for a single blob, stored in the chunked record with the given record key.
+    Chunked(String),
+}
+
+/// BlobStore uploads files as blobs, deduplicating them by the canonical path of the file
+/// and by the CID of its content.
+/// Files bigger than chunk_size bytes are split in chunks.
+pub struct BlobStore {
+    chunk_size: usize,
+    by_path: HashMap<PathBuf, Uploaded>,
+    by_cid: HashMap<String, Uploaded>,
+}
+
+impl BlobStore {
+    pub fn new(chunk_size: usize) -> Self {
+        BlobStore {
+            chunk_size,
+            by_path: HashMap::new(),
+            by_cid: HashMap::new(),
+        }
+    }
+
+    /// blobs returns every blob uploaded so far, each one once.
+    pub fn blobs(&self) -> Vec<BlobRef> {
+        self.by_cid
+            .values()
+            .filter_map(|u| match u {
+                Uploaded::Blob(blob, _) => Some(blob.clone()),
+                Uploaded::Chunked(_) => None,
+            })
+            .collect()
+    }
+
+    /// upload stores the content of the file at path on the PDS, uploading it only if
+    /// no other file with the same content has been uploaded before.
+    pub async fn upload(&mut self, identity_data: &IdentityData, path: &Path) -> Result<Uploaded> {
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("cannot open {:?}", path))?;
+
+        if let Some(uploaded) = self.by_path.get(&path) {
+            return Ok(uploaded.clone());
+        }
+
+        let content = std::fs::read(path.clone())
+            .with_context(|| format!("cannot open {:?}", path.clone()))?;
+
+        let cid = atproto::blob_cid(&content);
+
+        let ret = match self.by_cid.get(&cid) {
+            Some(uploaded) => {
+                log::debug!(
+                    "{:?} has the same content as blob {}, reusing it",
+                    path,
+                    cid
+                );
+                uploaded.clone()
+            }
+            None => {
+                let ext = path.extension().map(|s| s.to_str().unwrap().to_string());
+
+                let uploaded = match split(&content, self.chunk_size) {
+                    Some(chunks) => {
+                        let rkey = identity_data.upload_chunked(&content, chunks, ext).await?;
+
+                        log::debug!("Uploading {:?} in chunks to record {}", path, rkey);
+
+                        Uploaded::Chunked(rkey)
+                    }
+                    None => {
+                        let (blob, blob_ref) = identity_data.upload_blob(content, ext).await?;
+
+                        log::debug!("Uploading {:?} to blob ref {}", path, blob_ref);
+
+                        Uploaded::Blob(blob, blob_ref)
+                    }
+                };
+
+                self.by_cid.insert(cid, uploaded.clone());
+                uploaded
+            }
+        };
+
+        self.by_path.insert(path, ret.clone());
+
+        Ok(ret)
+    }
+}
+
+/// split returns the chunks content is uploaded in, in order, or None if it fits in a single blob
+/// of chunk_size bytes.
+fn split(content: &[u8], chunk_size: usize) -> Option<Vec<&[u8]>> {
+    match content.len() > chunk_size {
+        true => Some(content.chunks(chunk_size).collect()),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_files_that_fit_whole() {
+        assert!(split(b"", 4).is_none());
+        assert!(split(b"abcd", 4).is_none());
+    }
+
+    #[test]
+    fn splits_bigger_files_in_order() {
+        let chunks = split(b"abcdefghij", 4).unwrap();
+
+        assert_eq!(chunks, vec![&b"abcd"[..], b"efgh", b"ij"]);
+        assert_eq!(chunks.concat(), b"abcdefghij");
+    }
+
+    #[test]
+    fn splits_in_full_chunks() {
+        let chunks = split(b"abcdefgh", 4).unwrap();
+
+        assert_eq!(chunks, vec![&b"abcd"[..], b"efgh"]);
+    }
+}
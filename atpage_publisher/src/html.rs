@@ -1,52 +1,21 @@
 use dom_query::Selection;
-use std::{path::PathBuf, string::FromUtf8Error};
+use std::string::FromUtf8Error;
 use thiserror::{self, Error};
 
-const EDITABLE_ATTRS: [&'static str; 2] = ["href", "src"];
+const EDITABLE_ATTRS: [&str; 2] = ["href", "src"];
 
 type EditRet = Result<Option<String>, Error>;
 
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Io error")]
-    IoError(#[from] std::io::Error),
+    Io(#[from] std::io::Error),
 
     #[error("HTML content error")]
-    FormatError(#[from] FromUtf8Error),
+    Format(#[from] FromUtf8Error),
 
     #[error("Error")]
-    AnyError(#[from] anyhow::Error),
-}
-
-pub fn walk_html(dir: PathBuf) -> Result<Vec<PathBuf>, Error> {
-    let dir_iter = std::fs::read_dir(dir)?;
-
-    let mut ret = vec![];
-    for dir in dir_iter {
-        let dir = dir?;
-
-        let metadata = dir.metadata()?;
-
-        let path = dir.path();
-
-        match metadata.is_dir() {
-            true => {
-                let mut walked = walk_html(path)?;
-                ret.append(&mut walked)
-            }
-            false => {
-                match path.extension() {
-                    Some(ex) => match ex.to_str().unwrap() {
-                        "html" | "htm" => ret.push(path),
-                        _ => (),
-                    },
-                    None => (), // continue, no extension
-                }
-            }
-        }
-    }
-
-    Ok(ret)
+    Any(#[from] anyhow::Error),
 }
 
 /// scan_html scans the HTML contained in data, and runs editor on the content of the tree.
@@ -10,15 +10,32 @@
 // Cool right?
 use bsky_sdk::api::types::{self, string::AtIdentifier, Collection};
 
-pub const COLLECTION_NAME: &'static str = "industries.geesawra.atpage";
+pub const COLLECTION_NAME: &str = shared::atproto::PAGE_COLLECTION;
+pub const SITE_COLLECTION_NAME: &str = shared::atproto::SITE_COLLECTION;
+pub const CHUNKED_COLLECTION_NAME: &str = shared::atproto::CHUNKED_COLLECTION;
+
+/// maxSize of a page's content blob, in bytes.
+pub const CONTENT_MAX_SIZE: usize = 1_000_000;
+
+/// maxSize of blobs embedded in pages or published as standalone assets, in bytes.
+pub const EMBED_MAX_SIZE: usize = 100_000_000;
+
+/// Maximum size of a chunked record, in bytes.
+pub const CHUNKED_MAX_SIZE: usize = 1_000_000_000;
 
 const CREATE_RECORD_NDIS: &str = "com.atproto.repo.createRecord";
+const PUT_RECORD_NDIS: &str = "com.atproto.repo.putRecord";
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(tag = "$type")]
+#[allow(clippy::enum_variant_names)]
 pub enum KnownRecord {
     #[serde(rename = "industries.geesawra.atpage")]
     IndustriesGeesawraWebsitePage(Box<Record>),
+    #[serde(rename = "industries.geesawra.atpage.site")]
+    IndustriesGeesawraWebsiteSite(Box<SiteRecord>),
+    #[serde(rename = "industries.geesawra.atpage.chunked")]
+    IndustriesGeesawraWebsiteChunked(Box<ChunkedRecord>),
 }
 impl From<Record> for KnownRecord {
     fn from(record: Record) -> Self {
@@ -31,11 +48,33 @@ impl From<Page> for KnownRecord {
     }
 }
 
+impl From<Site> for KnownRecord {
+    fn from(record_data: Site) -> Self {
+        KnownRecord::IndustriesGeesawraWebsiteSite(Box::new(record_data.into()))
+    }
+}
+
+impl From<Chunked> for KnownRecord {
+    fn from(record_data: Chunked) -> Self {
+        KnownRecord::IndustriesGeesawraWebsiteChunked(Box::new(record_data.into()))
+    }
+}
+
 impl Collection for Page {
-    const NSID: &'static str = COLLECTION_NAME;
+    const NSID: &str = COLLECTION_NAME;
     type Record = Record;
 }
 
+impl Collection for Site {
+    const NSID: &str = SITE_COLLECTION_NAME;
+    type Record = SiteRecord;
+}
+
+impl Collection for Chunked {
+    const NSID: &str = CHUNKED_COLLECTION_NAME;
+    type Record = ChunkedRecord;
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct Page {
@@ -43,9 +82,70 @@ pub struct Page {
     pub content: types::BlobRef,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub embeds: Option<Vec<types::BlobRef>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<types::string::Datetime>,
 }
 
 pub type Record = types::Object<Page>;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Site {
+    pub assets: Vec<Asset>,
+    /// Path index mapping the original path of every page to its record key.
+    pub pages: Vec<SitePage>,
+    /// Manifest of the blobs pages and assets reference, carrying their MIME type.
+    pub blobs: Vec<types::BlobRef>,
+    /// Record key of the page served when something doesn't exist.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub not_found: Option<String>,
+    /// Record key of the page served when the PDS fails to serve something.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_error: Option<String>,
+    /// Security headers served along with every page and file of the site.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub headers: Option<Vec<Header>>,
+    /// Domains the site may be served at by renderers looking sites up by domain.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub domains: Option<Vec<String>>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Header {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SitePage {
+    pub path: String,
+    pub rkey: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Asset {
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blob: Option<types::BlobRef>,
+    /// Record key of the chunked record holding the asset, if it doesn't fit in a single blob.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunked: Option<String>,
+}
+
+pub type SiteRecord = types::Object<Site>;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Chunked {
+    pub mime_type: String,
+    pub size: usize,
+    pub chunks: Vec<types::BlobRef>,
+}
+
+pub type ChunkedRecord = types::Object<Chunked>;
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct InputData {
@@ -95,3 +195,52 @@ pub fn post_page(page: PageData) -> atrium_xrpc::XrpcRequest<(), InputData> {
         encoding: Some(String::from("application/json")),
     }
 }
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SiteData {
+    pub site: Site,
+    pub id: AtIdentifier,
+}
+
+impl From<SiteData> for InputData {
+    fn from(value: SiteData) -> Self {
+        InputData {
+            collection: Site::nsid(),
+            record: value.site.into(),
+            repo: value.id,
+            rkey: Some(shared::atproto::SITE_RKEY.to_string()),
+            swap_commit: None,
+            validate: None,
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ChunkedData {
+    pub chunked: Chunked,
+    pub id: AtIdentifier,
+    pub rkey: String,
+}
+
+impl From<ChunkedData> for InputData {
+    fn from(value: ChunkedData) -> Self {
+        InputData {
+            collection: Chunked::nsid(),
+            record: value.chunked.into(),
+            repo: value.id,
+            rkey: Some(value.rkey),
+            swap_commit: None,
+            validate: None,
+        }
+    }
+}
+
+pub fn put_record(record: impl Into<InputData>) -> atrium_xrpc::XrpcRequest<(), InputData> {
+    atrium_xrpc::XrpcRequest {
+        method: http::Method::POST,
+        nsid: PUT_RECORD_NDIS.into(),
+        parameters: None,
+        input: Some(atrium_xrpc::InputDataOrBytes::Data(record.into())),
+        encoding: Some(String::from("application/json")),
+    }
+}
@@ -1,14 +1,28 @@
 use anyhow::{anyhow, Context, Result};
-use atrium_api::types::{self, string::AtIdentifier, BlobRef};
+use atrium_api::types::{self, string::AtIdentifier};
 use clap::Parser;
-use html::{page_title, scan_html, walk_html};
+use html::{page_title, scan_html};
 use shared::cli;
-use std::{collections::HashMap, path::PathBuf, str::FromStr, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
 use tokio::sync::Mutex;
 
 mod atproto;
+mod blobs;
 mod html;
 mod lexicon;
+mod preflight;
+mod source;
+
+/// Page served in place of missing pages and files.
+const NOT_FOUND_PAGE: &str = "/404.html";
+
+/// Page served when the PDS fails to serve a page or file.
+const SERVER_ERROR_PAGE: &str = "/50x.html";
 
 #[derive(Clone)]
 struct PageData {
@@ -33,7 +47,23 @@ async fn main() -> Result<()> {
             login_data,
             src,
             extra_head: _,
-        } => post(login_data, src).await,
+            exclude,
+            include,
+            max_blob_size,
+            header,
+            domain,
+        } => {
+            post(
+                login_data,
+                src,
+                exclude,
+                include,
+                max_blob_size,
+                header,
+                domain,
+            )
+            .await
+        }
         cli::Command::Nuke(login_data) => nuke(login_data).await,
         cli::Command::Compile {
             at_uri: _,
@@ -61,23 +91,86 @@ async fn nuke(ld: cli::LoginData) -> Result<()> {
     Ok(())
 }
 
-async fn post(ld: cli::LoginData, src: String) -> Result<()> {
-    let content_dir = PathBuf::from_str(&src.clone()).unwrap();
+async fn post(
+    ld: cli::LoginData,
+    src: String,
+    exclude: Vec<String>,
+    include: Vec<String>,
+    max_blob_size: Option<usize>,
+    header: Vec<String>,
+    domain: Vec<String>,
+) -> Result<()> {
+    let headers = site_headers(&header)?;
+    let domains = site_domains(&domain)?;
+
+    let content_dir = PathBuf::from_str(&src.clone())
+        .unwrap()
+        .canonicalize()
+        .with_context(|| format!("cannot open source directory {src}"))?;
+
+    let source = Arc::new(source::Source::new(
+        content_dir.clone(),
+        &exclude,
+        &include,
+    )?);
 
     let identity_data = Arc::new(Mutex::new(
         atproto::IdentityData::login(ld.username.clone(), ld.password.clone(), ld.pds.clone())
             .await?,
     ));
 
+    // pre-flight: make sure every file fits in a blob before uploading anything
+    let limits = preflight::Limits {
+        blob: max_blob_size.unwrap_or(preflight::DEFAULT_BLOB_LIMIT),
+    };
+
+    preflight::check(&files_to_upload(&source, &content_dir).await?, &limits)?;
+
     let pages = Arc::new(Mutex::new(HashMap::new()));
-    let dedup = Arc::new(Mutex::new(HashMap::<String, (BlobRef, String)>::new()));
+    let blobs = Arc::new(Mutex::new(blobs::BlobStore::new(limits.embed())));
+
+    // step 0: upload standalone assets, pages referencing them will reuse them
+    let mut assets = vec![];
+    for f in source.assets() {
+        let path = source.site_path(f)?;
+
+        let uploaded = blobs
+            .lock()
+            .await
+            .upload(&*identity_data.lock().await, f)
+            .await?;
+
+        let asset = match uploaded {
+            blobs::Uploaded::Blob(blob, blob_ref) => {
+                log::info!("Uploaded asset {}: {}", path, blob_ref);
+
+                lexicon::Asset {
+                    path,
+                    blob: Some(blob),
+                    chunked: None,
+                }
+            }
+            blobs::Uploaded::Chunked(rkey) => {
+                log::info!("Uploaded asset {} in chunks: {}", path, rkey);
+
+                lexicon::Asset {
+                    path,
+                    blob: None,
+                    chunked: Some(rkey),
+                }
+            }
+        };
+
+        assets.push(asset);
+    }
 
     // step 1: upload blobs as they appear alongside raw pages
-    for f in walk_html(content_dir.clone())? {
+    for f in source.html_files() {
         let refs = Arc::new(Mutex::new(vec![]));
         log::debug!("Processing blobs for page {:?}", f);
 
         let pages = pages.clone();
+        let page_dir = f.parent().unwrap().to_path_buf();
 
         let content = std::fs::read(f.clone())?;
         let content = String::from_utf8(content)?;
@@ -93,52 +186,35 @@ async fn post(ld: cli::LoginData, src: String) -> Result<()> {
         };
 
         let page_content = scan_html(content.clone(), async |src, is_a| {
-            if is_a && !(src.ends_with(".jpg") || src.ends_with(".png")) {
-                // ignore <a> at this point
-                return Ok(None);
-            }
-
             let identity_data = identity_data.clone();
             let refs = refs.clone();
-            let dedup = dedup.clone();
-            let content_dir = content_dir.clone();
-
-            if let Some(blob) = dedup.lock().await.get(&src.clone()) {
-                let (blob, blob_ref) = blob;
-                refs.lock().await.push(blob.clone());
-                return Ok(Some(
-                    identity_data.lock().await.format_blob_uri(blob_ref.clone()),
-                ));
-            }
-
-            let mut src_path = PathBuf::from_str(&src.clone()).unwrap();
-            if src_path.is_absolute() {
-                src_path = src_path.strip_prefix("/").unwrap().to_path_buf();
-            }
-
-            let blob_path = content_dir.join(src_path.clone());
-
-            let blob_content = std::fs::read(blob_path.clone())
-                .with_context(|| format!("cannot open {:?}", blob_path.clone()))?;
+            let blobs = blobs.clone();
+            let source = source.clone();
 
-            let blob_ext = blob_path
-                .extension()
-                .map(|s| s.to_str().unwrap().to_string());
+            let blob_path = match blob_path(&src, is_a, &content_dir, &page_dir) {
+                Some(p) => p,
+                None => return Ok(None),
+            };
 
-            let identity_data = identity_data.lock().await;
-
-            // TODO(geesawra): check if a given blob_content is already on the PDS?
-            let (blob, blob_ref) = identity_data.upload_blob(blob_content, blob_ext).await?;
-
-            log::debug!("Uploading {:?} to blob ref {}", blob_path, blob_ref);
+            if blob_path.exists() && !source.contains(&blob_path) {
+                return Ok(None);
+            }
 
-            refs.lock().await.push(blob.clone());
-            dedup
+            let uploaded = blobs
                 .lock()
                 .await
-                .insert(src.clone(), (blob, blob_ref.clone()));
+                .upload(&*identity_data.lock().await, &blob_path)
+                .await?;
+
+            let identity_data = identity_data.lock().await;
 
-            Ok(Some(identity_data.format_blob_uri(blob_ref)))
+            Ok(Some(match uploaded {
+                blobs::Uploaded::Blob(blob, blob_ref) => {
+                    refs.lock().await.push(blob);
+                    identity_data.format_blob_uri(blob_ref)
+                }
+                blobs::Uploaded::Chunked(rkey) => identity_data.format_chunked_uri(rkey),
+            }))
         })
         .await?;
 
@@ -154,18 +230,16 @@ async fn post(ld: cli::LoginData, src: String) -> Result<()> {
         pages.lock().await.insert(stripped_path, page.clone());
     }
 
-    // step 2: overwrite <a> tags
-    let mut index_address = String::new();
+    // step 2: overwrite <a> tags, and make sure pages still fit in a blob now that their links
+    // are rewritten
+    let mut rewritten = vec![];
 
-    for f in walk_html(content_dir.clone())? {
+    for f in source.html_files() {
         let stripped_path = to_html_path(f.clone(), content_dir.clone())?;
 
         let page_data = {
             let maybe_page = pages.lock().await;
-            match maybe_page.get(&stripped_path) {
-                Some(p) => Some(p.clone()),
-                None => None,
-            }
+            maybe_page.get(&stripped_path).cloned()
         };
 
         let page_data = match page_data {
@@ -178,16 +252,14 @@ async fn post(ld: cli::LoginData, src: String) -> Result<()> {
                 return Ok(None);
             }
 
-            let identity_data = identity_data.clone();
-
             let pages = pages.clone();
 
-            if let Some(page) = pages.lock().await.get(&PathBuf::from_str(&attr).unwrap()) {
-                let res = identity_data.lock().await;
+            // the renderer serves pages at their original path, relative links to other pages
+            // are made absolute so that they keep working from /at/ URLs too
+            let link = site_link(&attr, &stripped_path);
 
-                let data = res.format_record_uri(page.rkey().unwrap());
-
-                return Ok(Some(data));
+            if pages.lock().await.contains_key(&link) {
+                return Ok(Some(link.to_string_lossy().to_string()));
             }
 
             Ok(None)
@@ -195,10 +267,31 @@ async fn post(ld: cli::LoginData, src: String) -> Result<()> {
 
         let page_content = maybe_page_content.await?;
 
+        rewritten.push((f.clone(), stripped_path, page_data, page_content));
+    }
+
+    preflight::fits(
+        &rewritten
+            .iter()
+            .map(|(f, _, _, content)| preflight::File {
+                path: f.clone(),
+                size: content.len(),
+                page: true,
+            })
+            .collect::<Vec<preflight::File>>(),
+        &limits,
+    )?;
+
+    let mut index_address = String::new();
+    let mut site_pages = vec![];
+    let mut not_found = None;
+    let mut server_error = None;
+
+    for (f, stripped_path, page_data, page_content) in rewritten {
         let (page_content_ref, _) = identity_data
             .lock()
             .await
-            .upload_blob(page_content.into_bytes(), Some("text/html".to_string()))
+            .upload_blob_with_mime(page_content.into_bytes(), Some("text/html".to_string()))
             .await?;
 
         let new_page_data = lexicon::PageData {
@@ -206,6 +299,7 @@ async fn post(ld: cli::LoginData, src: String) -> Result<()> {
                 title: page_data.title.clone(),
                 content: page_content_ref,
                 embeds: page_data.embeds.clone(),
+                updated_at: Some(types::string::Datetime::now()),
             },
             id: page_data.did.clone(),
             rkey: page_data.rkey(),
@@ -222,14 +316,202 @@ async fn post(ld: cli::LoginData, src: String) -> Result<()> {
         if f.ends_with("index.html") {
             index_address = res.uri.to_string();
         }
+
+        site_pages.push(lexicon::SitePage {
+            path: stripped_path.to_string_lossy().to_string(),
+            rkey: page_data.rkey().unwrap(),
+        });
+
+        if stripped_path == Path::new(NOT_FOUND_PAGE) {
+            not_found = page_data.rkey();
+        } else if stripped_path == Path::new(SERVER_ERROR_PAGE) {
+            server_error = page_data.rkey();
+        }
     }
 
+    // step 3: write the site record, which keeps standalone assets referenced, indexes pages by
+    // path and points to error pages
+    let did = identity_data.lock().await.did();
+    let res = identity_data
+        .lock()
+        .await
+        .put_record(lexicon::SiteData {
+            site: lexicon::Site {
+                assets,
+                pages: site_pages,
+                blobs: blobs.lock().await.blobs(),
+                not_found,
+                server_error,
+                headers,
+                domains,
+            },
+            id: did,
+        })
+        .await?;
+
+    log::info!("Uploaded site record: {}", res.uri);
+
     // not using log here, needs to be picked up by caller process;
     println!("ATPage index URI: {index_address}");
 
     Ok(())
 }
 
+/// site_headers parses headers given as "Name: value", making sure they're among the ones a site
+/// is allowed to set.
+fn site_headers(headers: &[String]) -> Result<Option<Vec<lexicon::Header>>> {
+    if headers.is_empty() {
+        return Ok(None);
+    }
+
+    headers
+        .iter()
+        .map(|h| {
+            let (name, value) = h
+                .split_once(':')
+                .ok_or(anyhow!("malformed header {h}, must be \"Name: value\""))?;
+
+            let name = shared::atproto::SITE_HEADERS
+                .iter()
+                .find(|n| n.eq_ignore_ascii_case(name.trim()))
+                .ok_or(anyhow!(
+                    "header {} can't be set by a site, allowed ones are: {}",
+                    name.trim(),
+                    shared::atproto::SITE_HEADERS.join(", ")
+                ))?;
+
+            Ok(lexicon::Header {
+                name: name.to_string(),
+                value: value.trim().to_string(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()
+        .map(Some)
+}
+
+/// site_domains normalizes the domains a site claims, which must be bare hostnames.
+fn site_domains(domains: &[String]) -> Result<Option<Vec<String>>> {
+    if domains.is_empty() {
+        return Ok(None);
+    }
+
+    domains
+        .iter()
+        .map(|d| {
+            let d = d.trim().trim_end_matches('.').to_lowercase();
+
+            let valid = d
+                .split('.')
+                .all(|l| !l.is_empty() && l.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'));
+
+            match valid {
+                true => Ok(d),
+                false => Err(anyhow!(
+                    "malformed domain {d}, must be a hostname like example.com"
+                )),
+            }
+        })
+        .collect::<Result<Vec<_>>>()
+        .map(Some)
+}
+
+/// blob_path returns the path of the file referenced by src in a page living in page_dir,
+/// or None if src must not be uploaded as a blob.
+fn blob_path(src: &str, is_a: bool, content_dir: &Path, page_dir: &Path) -> Option<PathBuf> {
+    if is_a && !(src.ends_with(".jpg") || src.ends_with(".png")) {
+        // ignore <a> at this point
+        return None;
+    }
+
+    // absolute references start from the website root, relative ones from the page's directory
+    Some(match src.strip_prefix("/") {
+        Some(abs) => content_dir.join(abs),
+        None => page_dir.join(src),
+    })
+}
+
+/// site_link returns the site path link points to, when found in the page at page_path.
+fn site_link(link: &str, page_path: &Path) -> PathBuf {
+    let joined = match link.starts_with("/") {
+        true => PathBuf::from(link),
+        false => page_path.parent().unwrap_or(Path::new("/")).join(link),
+    };
+
+    let mut ret = PathBuf::from("/");
+    for c in joined.components() {
+        match c {
+            std::path::Component::ParentDir => {
+                ret.pop();
+            }
+            std::path::Component::Normal(n) => ret.push(n),
+            _ => {}
+        }
+    }
+
+    ret
+}
+
+/// files_to_upload returns all the files that will be uploaded as blobs: pages, the files they
+/// reference and standalone assets.
+async fn files_to_upload(
+    source: &source::Source,
+    content_dir: &Path,
+) -> Result<Vec<preflight::File>> {
+    let mut seen = HashSet::new();
+    let mut files = vec![];
+
+    let mut push = |path: PathBuf, page: bool| -> Result<()> {
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("cannot open {:?}", path))?;
+
+        if seen.insert(path.clone()) {
+            let size = std::fs::metadata(path.clone())?.len() as usize;
+            files.push(preflight::File { path, size, page });
+        }
+
+        Ok(())
+    };
+
+    for f in source.assets() {
+        push(f.clone(), false)?;
+    }
+
+    for f in source.html_files() {
+        push(f.clone(), true)?;
+
+        let page_dir = f.parent().unwrap().to_path_buf();
+        let refs = Arc::new(Mutex::new(vec![]));
+
+        let content = String::from_utf8(std::fs::read(f.clone())?)?;
+
+        scan_html(content, async |src, is_a| {
+            let refs = refs.clone();
+
+            if let Some(p) = blob_path(&src, is_a, content_dir, &page_dir) {
+                refs.lock().await.push(p);
+            }
+
+            Ok(None)
+        })
+        .await?;
+
+        for r in refs.lock().await.iter() {
+            if r.exists() && !source.contains(r) {
+                log::warn!(
+                    "{:?} is excluded from the website, leaving the reference to it untouched",
+                    r
+                );
+                continue;
+            }
+
+            push(r.clone(), false)?;
+        }
+    }
+
+    Ok(files)
+}
+
 fn to_html_path(p: PathBuf, fs_base: PathBuf) -> Result<PathBuf> {
     let stripped_path = p.strip_prefix(fs_base);
     let stripped_path = PathBuf::from_str("/")
@@ -238,3 +520,63 @@ fn to_html_path(p: PathBuf, fs_base: PathBuf) -> Result<PathBuf> {
 
     Ok(stripped_path)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_links_against_the_page() {
+        let page = Path::new("/blog/post.html");
+
+        assert_eq!(
+            site_link("other.html", page),
+            PathBuf::from("/blog/other.html")
+        );
+        assert_eq!(
+            site_link("../index.html", page),
+            PathBuf::from("/index.html")
+        );
+        assert_eq!(site_link("/about.html", page), PathBuf::from("/about.html"));
+        assert_eq!(
+            site_link("./img/a.png", page),
+            PathBuf::from("/blog/img/a.png")
+        );
+    }
+
+    #[test]
+    fn keeps_links_inside_the_site() {
+        let page = Path::new("/post.html");
+
+        assert_eq!(
+            site_link("../../etc/passwd", page),
+            PathBuf::from("/etc/passwd")
+        );
+    }
+
+    #[test]
+    fn finds_referenced_files() {
+        let content = Path::new("/site");
+        let page = Path::new("/site/blog");
+
+        assert_eq!(
+            blob_path("/img/a.png", false, content, page),
+            Some(PathBuf::from("/site/img/a.png"))
+        );
+        assert_eq!(
+            blob_path("a.css", false, content, page),
+            Some(PathBuf::from("/site/blog/a.css"))
+        );
+    }
+
+    #[test]
+    fn only_follows_links_to_images() {
+        let content = Path::new("/site");
+
+        assert_eq!(
+            blob_path("b.jpg", true, content, content),
+            Some(PathBuf::from("/site/b.jpg"))
+        );
+        assert_eq!(blob_path("b.html", true, content, content), None);
+    }
+}
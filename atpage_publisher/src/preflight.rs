@@ -0,0 +1,212 @@
+use anyhow::{anyhow, Result};
+use std::path::PathBuf;
+
+use crate::lexicon;
+
+/// Blob size limit used unless --max-blob-size is given, matching the reference PDS default.
+/// PDSes don't advertise theirs: describeServer has no field for it.
+pub const DEFAULT_BLOB_LIMIT: usize = 5 * 1024 * 1024;
+
+/// Limits holds the maximum sizes, in bytes, of the blobs the publisher can upload.
+pub struct Limits {
+    /// Maximum size of any blob, as accepted by the PDS.
+    pub blob: usize,
+}
+
+impl Limits {
+    /// content returns the maximum size of a page's HTML content.
+    pub fn content(&self) -> usize {
+        self.blob.min(lexicon::CONTENT_MAX_SIZE)
+    }
+
+    /// embed returns the maximum size of a blob embedded in a page or published as an asset,
+    /// bigger files are split in chunks of this size.
+    pub fn embed(&self) -> usize {
+        self.blob.min(lexicon::EMBED_MAX_SIZE)
+    }
+
+    /// file returns the maximum size of a file embedded in a page or published as an asset,
+    /// once split in chunks.
+    pub fn file(&self) -> usize {
+        lexicon::CHUNKED_MAX_SIZE
+    }
+}
+
+/// File is a file that will be uploaded to the PDS as a blob.
+pub struct File {
+    pub path: PathBuf,
+    pub size: usize,
+    pub page: bool,
+}
+
+/// check makes sure every file fits in a blob, returning an error listing the offending files otherwise.
+pub fn check(files: &[File], limits: &Limits) -> Result<()> {
+    let total = files.iter().map(|f| f.size).sum::<usize>();
+
+    log::info!(
+        "Publishing {} files, {} in total",
+        files.len(),
+        human_size(total)
+    );
+
+    let chunked = files
+        .iter()
+        .filter(|f| !f.page && f.size > limits.embed())
+        .count();
+
+    if chunked > 0 {
+        log::info!(
+            "{} files are bigger than {} and will be split in chunks",
+            chunked,
+            human_size(limits.embed())
+        );
+    }
+
+    fits(files, limits)
+}
+
+/// fits returns an error listing the files that don't fit in a blob, if any.
+pub fn fits(files: &[File], limits: &Limits) -> Result<()> {
+    let offending = files
+        .iter()
+        .filter_map(|f| {
+            let limit = match f.page {
+                true => limits.content(),
+                false => limits.file(),
+            };
+
+            match f.size > limit {
+                true => Some((f, limit)),
+                false => None,
+            }
+        })
+        .collect::<Vec<(&File, usize)>>();
+
+    if offending.is_empty() {
+        return Ok(());
+    }
+
+    let width = offending
+        .iter()
+        .map(|(f, _)| f.path.display().to_string().len())
+        .max()
+        .unwrap_or_default()
+        .max("FILE".len());
+
+    let mut table = format!(
+        "{:<width$}  {:>10}  {:>10}  SUGGESTION\n",
+        "FILE", "SIZE", "LIMIT"
+    );
+
+    for (f, limit) in offending.iter() {
+        table.push_str(&format!(
+            "{:<width$}  {:>10}  {:>10}  {}\n",
+            f.path.display().to_string(),
+            human_size(f.size),
+            human_size(*limit),
+            suggestion(f),
+        ));
+    }
+
+    Err(anyhow!(
+        "{} files are too big to be uploaded, nothing has been published:\n\n{}",
+        offending.len(),
+        table
+    ))
+}
+
+fn suggestion(f: &File) -> &'static str {
+    if f.page {
+        return "split the page in smaller ones";
+    }
+
+    let mime = mime_guess::from_path(&f.path).first_or_octet_stream();
+
+    match mime.type_().as_str() {
+        "image" => "compress or resize the image",
+        "video" | "audio" => "transcode at a lower bitrate, or host it elsewhere and link to it",
+        _ => "exclude it with --exclude or .atpageignore, or host it elsewhere and link to it",
+    }
+}
+
+fn human_size(bytes: usize) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{} {}", bytes, UNITS[0]),
+        _ => format!("{:.1} {}", size, UNITS[unit]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: Limits = Limits { blob: 2_000_000 };
+
+    fn file(path: &str, size: usize, page: bool) -> File {
+        File {
+            path: PathBuf::from(path),
+            size,
+            page,
+        }
+    }
+
+    #[test]
+    fn limits_pages_to_their_content_size() {
+        assert!(fits(&[file("index.html", 1_000_000, true)], &LIMITS).is_ok());
+        assert!(fits(&[file("index.html", 1_000_001, true)], &LIMITS).is_err());
+    }
+
+    #[test]
+    fn lets_files_bigger_than_a_blob_be_chunked() {
+        assert!(fits(&[file("video.mp4", 900_000_000, false)], &LIMITS).is_ok());
+        assert!(fits(&[file("video.mp4", 1_000_000_001, false)], &LIMITS).is_err());
+    }
+
+    #[test]
+    fn lists_only_the_offending_files() {
+        let files = [
+            file("index.html", 10, true),
+            file("big.html", 2_000_000, true),
+            file("huge.png", 2_000_000_000, false),
+        ];
+
+        let err = fits(&files, &LIMITS).unwrap_err().to_string();
+
+        assert!(err.starts_with("2 files are too big"));
+        assert!(err.contains("big.html"));
+        assert!(err.contains("huge.png"));
+        assert!(!err.contains("index.html"));
+        assert!(err.contains("split the page in smaller ones"));
+        assert!(err.contains("compress or resize the image"));
+    }
+
+    #[test]
+    fn suggests_by_file_type() {
+        assert_eq!(
+            suggestion(&file("a.mp3", 0, false)),
+            "transcode at a lower bitrate, or host it elsewhere and link to it"
+        );
+        assert_eq!(
+            suggestion(&file("a.zip", 0, false)),
+            "exclude it with --exclude or .atpageignore, or host it elsewhere and link to it"
+        );
+    }
+
+    #[test]
+    fn prints_sizes_in_binary_units() {
+        assert_eq!(human_size(0), "0 B");
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(5 * 1024 * 1024), "5.0 MiB");
+        assert_eq!(human_size(3 * 1024 * 1024 * 1024 * 1024), "3072.0 GiB");
+    }
+}
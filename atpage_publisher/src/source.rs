@@ -0,0 +1,240 @@
+use anyhow::{Context, Result};
+use ignore::{overrides::OverrideBuilder, WalkBuilder};
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+/// Name of the gitignore-style files holding exclusion patterns, looked up in every directory of the source tree.
+pub const IGNORE_FILENAME: &str = ".atpageignore";
+
+/// Source holds the files of a website directory that aren't excluded by ignore files, --exclude globs
+/// or by being hidden.
+/// Files matched by --include globs are standalone assets, and are kept even if hidden.
+pub struct Source {
+    root: PathBuf,
+    files: HashSet<PathBuf>,
+    pages: Vec<PathBuf>,
+    assets: Vec<PathBuf>,
+}
+
+impl Source {
+    pub fn new(root: PathBuf, excludes: &[String], includes: &[String]) -> Result<Self> {
+        let mut files = walk(&root, OverrideBuilder::new(&root), excludes, true)?;
+
+        let mut pages = files
+            .iter()
+            .filter(|p| matches!(p.extension().and_then(|e| e.to_str()), Some("html" | "htm")))
+            .cloned()
+            .collect::<Vec<PathBuf>>();
+        pages.sort();
+
+        let mut assets = vec![];
+        if !includes.is_empty() {
+            let mut overrides = OverrideBuilder::new(&root);
+            for i in includes {
+                let i = i.trim_end_matches("/");
+                for glob in [i.to_string(), format!("{i}/**")] {
+                    overrides
+                        .add(&glob)
+                        .with_context(|| format!("invalid include pattern {i}"))?;
+                }
+            }
+
+            assets = walk(&root, overrides, excludes, false)?
+                .into_iter()
+                .collect::<Vec<PathBuf>>();
+            assets.sort();
+
+            files.extend(assets.iter().cloned());
+        }
+
+        Ok(Source {
+            root,
+            files,
+            pages,
+            assets,
+        })
+    }
+
+    /// html_files returns all the .html and .htm files in the source tree, sorted by path.
+    pub fn html_files(&self) -> Vec<PathBuf> {
+        self.pages.clone()
+    }
+
+    /// assets returns the files matched by --include globs, sorted by path.
+    pub fn assets(&self) -> &[PathBuf] {
+        &self.assets
+    }
+
+    /// site_path returns the absolute path at which path is found on the website.
+    pub fn site_path(&self, path: &Path) -> Result<String> {
+        let stripped = path.strip_prefix(&self.root)?;
+
+        Ok(format!("/{}", stripped.to_string_lossy()))
+    }
+
+    /// contains returns true if path points to a file that can be published.
+    pub fn contains(&self, path: &Path) -> bool {
+        match path.canonicalize() {
+            Ok(p) => self.files.contains(&p),
+            Err(_) => false,
+        }
+    }
+}
+
+fn walk(
+    root: &Path,
+    mut overrides: OverrideBuilder,
+    excludes: &[String],
+    skip_hidden: bool,
+) -> Result<HashSet<PathBuf>> {
+    for e in excludes {
+        overrides
+            .add(&format!("!{e}"))
+            .with_context(|| format!("invalid exclude pattern {e}"))?;
+    }
+
+    let walker = WalkBuilder::new(root)
+        .standard_filters(false)
+        .hidden(skip_hidden)
+        .add_custom_ignore_filename(IGNORE_FILENAME)
+        .overrides(overrides.build()?)
+        .filter_entry(|e| e.file_name() != ".git")
+        .build();
+
+    let mut files = HashSet::new();
+    for entry in walker {
+        let entry = entry?;
+
+        if !entry.file_type().map(|ft| ft.is_file()).unwrap_or(false) {
+            continue;
+        }
+
+        files.insert(entry.path().canonicalize()?);
+    }
+
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// tree creates a directory holding files, each with its path as content, and returns its
+    /// canonical path.
+    fn tree(name: &str, files: &[&str]) -> PathBuf {
+        let root =
+            std::env::temp_dir().join(format!("atpage-source-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+
+        for f in files {
+            let path = root.join(f);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, f).unwrap();
+        }
+
+        root.canonicalize().unwrap()
+    }
+
+    fn pages(source: &Source) -> Vec<String> {
+        source
+            .html_files()
+            .iter()
+            .map(|p| source.site_path(p).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn finds_pages() {
+        let root = tree("pages", &["index.html", "blog/post.htm", "style.css"]);
+
+        let source = Source::new(root.clone(), &[], &[]).unwrap();
+
+        assert_eq!(pages(&source), vec!["/blog/post.htm", "/index.html"]);
+        assert!(source.contains(&root.join("style.css")));
+        assert!(source.assets().is_empty());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn skips_ignored_files() {
+        let root = tree(
+            "ignored",
+            &[
+                "index.html",
+                "drafts/wip.html",
+                "notes.txt",
+                ".atpageignore",
+                "blog/post.html",
+                "blog/private.html",
+                "blog/.atpageignore",
+            ],
+        );
+        fs::write(root.join(".atpageignore"), "drafts/\n*.txt\n").unwrap();
+        fs::write(root.join("blog/.atpageignore"), "private.html\n").unwrap();
+
+        let source = Source::new(root.clone(), &[], &[]).unwrap();
+
+        assert_eq!(pages(&source), vec!["/blog/post.html", "/index.html"]);
+        assert!(!source.contains(&root.join("notes.txt")));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn skips_hidden_files_unless_included() {
+        let root = tree(
+            "hidden",
+            &["index.html", ".secret.html", ".well-known/atproto-did"],
+        );
+
+        let source = Source::new(root.clone(), &[], &[]).unwrap();
+
+        assert_eq!(pages(&source), vec!["/index.html"]);
+        assert!(!source.contains(&root.join(".well-known/atproto-did")));
+
+        let source = Source::new(root.clone(), &[], &[".well-known/".to_string()]).unwrap();
+
+        assert_eq!(pages(&source), vec!["/index.html"]);
+        assert_eq!(source.assets(), &[root.join(".well-known/atproto-did")][..]);
+        assert!(source.contains(&root.join(".well-known/atproto-did")));
+        assert!(!source.contains(&root.join(".secret.html")));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn skips_excluded_files() {
+        let root = tree(
+            "excluded",
+            &[
+                "index.html",
+                "old/page.html",
+                "assets/a.png",
+                "assets/b.raw",
+            ],
+        );
+
+        let excludes = ["old/".to_string(), "*.raw".to_string()];
+        let source = Source::new(root.clone(), &excludes, &["assets".to_string()]).unwrap();
+
+        assert_eq!(pages(&source), vec!["/index.html"]);
+        assert_eq!(source.assets(), &[root.join("assets/a.png")][..]);
+        assert!(!source.contains(&root.join("assets/b.raw")));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn refuses_invalid_patterns() {
+        let root = tree("invalid", &["index.html"]);
+
+        assert!(Source::new(root.clone(), &["a[".to_string()], &[]).is_err());
+        assert!(Source::new(root.clone(), &[], &["a[".to_string()]).is_err());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
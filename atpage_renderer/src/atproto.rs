@@ -1,206 +1,568 @@
+use crate::{
+    cache, config,
+    host::{self, Host, Partial, Streamed, Worker},
+    ipld,
+    range::Range,
+    serve::Site,
+    verify,
+};
+use futures::{stream::LocalBoxStream, Stream, StreamExt};
 use js_sys::Uint8Array;
-use shared::atproto::ATURL;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::JsFuture;
-use web_sys::{Request, RequestInit, RequestMode, Response, Url, WorkerGlobalScope};
+use web_sys::{
+    Headers, ReadableStream, Request, RequestInit, RequestMode, Response, ResponseInit,
+    WorkerGlobalScope,
+};
 
-const PLC_DIRECTORY: &'static str = "https://plc.directory";
-const BSKY_SOCIAL: &'static str = "https://bsky.social";
+const BLOB_KIND: &str = "blob";
+const RECORD_KIND: &str = "record";
 
-#[allow(dead_code)]
 #[derive(Debug)]
 pub enum Error {
     NoDIDFound(String),
     NoPDSFound(String),
-    JSError(JsValue),
-    JSSerdeError(serde_wasm_bindgen::Error),
+    UnsupportedDID(String),
+    HandleMismatch(String),
+    NoAssetFound(String),
+    NoBlobFound(String),
+    NoRecordFound(String),
+    XRPCError(String),
+    VerificationFailed(String),
+    MalformedData(ipld::Error),
+    MalformedRecord(String),
+    JSError(String),
+    FetchError(String),
+    JSSerdeError(String),
     MalformedATURL(shared::atproto::Error),
+    UnclaimedDomain(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NoDIDFound(h) => write!(f, "can't resolve {} to a DID", h),
+            Error::NoPDSFound(d) => write!(f, "no PDS found for {}", d),
+            Error::UnsupportedDID(d) => write!(f, "unsupported DID {}", d),
+            Error::HandleMismatch(e) => write!(f, "{}", e),
+            Error::NoAssetFound(p) => write!(f, "no asset at {}", p),
+            Error::NoBlobFound(c) => write!(f, "no blob {}", c),
+            Error::NoRecordFound(u) => write!(f, "no record at {}", u),
+            Error::XRPCError(e) => write!(f, "PDS error: {}", e),
+            Error::VerificationFailed(e) => write!(f, "verification failed: {}", e),
+            Error::MalformedData(e) => write!(f, "{}", e),
+            Error::MalformedRecord(r) => write!(f, "malformed record {}", r),
+            Error::JSError(e) => write!(f, "request failed: {}", e),
+            Error::FetchError(e) => write!(f, "request failed: {}", e),
+            Error::JSSerdeError(e) => write!(f, "malformed response: {}", e),
+            Error::MalformedATURL(e) => write!(f, "malformed AT URI: {}", e),
+            Error::UnclaimedDomain(d) => write!(f, "no site claims {}", d),
+        }
+    }
 }
 
 impl From<JsValue> for Error {
     fn from(value: JsValue) -> Self {
-        Self::JSError(value)
+        // JavaScript values can't be sent between threads, unlike errors
+        Self::JSError(format!("{:?}", value))
+    }
+}
+
+impl From<ipld::Error> for Error {
+    fn from(value: ipld::Error) -> Self {
+        Self::MalformedData(value)
     }
 }
 
 impl From<serde_wasm_bindgen::Error> for Error {
     fn from(value: serde_wasm_bindgen::Error) -> Self {
-        Self::JSSerdeError(value)
+        Self::JSSerdeError(value.to_string())
     }
 }
 
-#[allow(dead_code)]
-pub struct Webpage {
-    pub content: String,
+pub fn pds_url(pds: String, method: String) -> String {
+    match ::url::Url::parse(&pds) {
+        Ok(mut u) => {
+            u.set_path(&format!("/xrpc/{}", method));
+            u.set_query(None);
+            u.to_string()
+        }
+        Err(_) => format!("{}/xrpc/{}", pds.trim_end_matches('/'), method),
+    }
+}
+
+pub fn url(base: String, args: &[(String, String)]) -> String {
+    if args.is_empty() {
+        return base;
+    }
+
+    match ::url::Url::parse(&base) {
+        Ok(mut u) => {
+            u.query_pairs_mut().extend_pairs(args);
+            u.to_string()
+        }
+        Err(_) => base,
+    }
 }
 
-fn plc_url(did: String) -> String {
-    let u = Url::new(&PLC_DIRECTORY).unwrap();
+/// Paths of the renderer's own files, which are always left to the network.
+const RENDERER_FILES: [&str; 3] = ["/index.js", "/sw.js", "/sw_nomod.js"];
+const RENDERER_DIRS: [&str; 2] = ["/mod/", "/nomod/"];
 
-    u.set_pathname(&did);
+/// at_path returns the percent-decoded path of u if it's an /at/ URL.
+pub fn at_path(u: &str) -> Option<String> {
+    let u = ::url::Url::parse(u).ok()?;
 
-    u.href()
+    match u.path().starts_with("/at/") {
+        true => decode(u.path()),
+        false => None,
+    }
 }
 
-fn bsky_url(method: String) -> String {
-    log::debug!("bsky url: {}", &xrpc_url(&BSKY_SOCIAL));
-    let u = Url::new_with_base(&method, &xrpc_url(&BSKY_SOCIAL)).unwrap();
+/// site_path returns the percent-decoded path u points to within the site served at plain paths,
+/// if u is a same-origin URL other than the renderer's own files and a site is served there.
+pub fn site_path(u: &str) -> Option<String> {
+    let worker = js_sys::global().dyn_into::<WorkerGlobalScope>().ok()?;
+    let u = ::url::Url::parse(u).ok()?;
+
+    if u.origin().ascii_serialization() != worker.location().origin() {
+        return None;
+    }
+
+    if RENDERER_DIRS.iter().any(|d| u.path().starts_with(d)) || RENDERER_FILES.contains(&u.path()) {
+        return None;
+    }
+
+    site()?;
 
-    u.href()
+    decode(u.path())
 }
 
-fn xrpc_url(base: &str) -> String {
-    let u = Url::new(base).unwrap();
+/// site returns the site served at plain paths of the worker's origin, if any.
+pub fn site() -> Option<Site> {
+    let config = config::get();
 
-    u.set_pathname("/xrpc/");
+    match (&config.site, config.domains) {
+        (Some(id), _) => Some(Site::Repo(id.clone())),
+        (None, true) => {
+            let worker = js_sys::global().dyn_into::<WorkerGlobalScope>().ok()?;
+            Some(Site::Domain(worker.location().hostname()))
+        }
+        (None, false) => None,
+    }
+}
 
-    u.href()
+fn decode(path: &str) -> Option<String> {
+    js_sys::decode_uri_component(path).ok()?.as_string()
 }
 
-fn pds_url(pds: String, method: String) -> String {
-    let u = Url::new_with_base(&method, &xrpc_url(&pds)).unwrap();
+/// blob returns the content of the blob with the given CID in did's repo, after checking that
+/// it actually hashes to that CID.
+/// Blobs are content-addressed and never go stale, so they're served from the cache when possible.
+pub async fn blob(pds: String, did: String, cid: String) -> Result<GetData, Error> {
+    let key = cache::key(BLOB_KIND, &cid);
+
+    if let Some(data) = cached_blob(&key).await? {
+        log::debug!("blob {} served from cache", cid);
+        return Ok(data);
+    }
+
+    let data = get(blob_url(pds, did, &cid), true).await?;
 
-    u.href()
+    check_blob(&cid, &key, data).await
 }
 
-fn url(base: String, args: &[(String, String)]) -> String {
-    if args.is_empty() {
-        return base;
+/// blob_stream returns the content of the blob with the given CID in did's repo as it's read from
+/// the cache or the PDS, ending with an error if it doesn't hash to that CID.
+/// Content coming from the PDS is cached as it's read, unless the PDS doesn't say how big it is:
+/// it can't be known to fit in the cache before it's all read.
+pub async fn blob_stream(
+    pds: String,
+    did: String,
+    cid: String,
+) -> Result<Streamed<LocalBoxStream<'static, Result<Vec<u8>, Error>>>, Error> {
+    let key = cache::key(BLOB_KIND, &cid);
+
+    if let Some(resp) = cache::lookup(&key).await {
+        if let Some(body) = resp.body() {
+            log::debug!("blob {} streamed from cache", cid);
+
+            return Ok(Streamed {
+                mime_type: resp.headers().get("Content-Type")?,
+                size: content_length(&resp)?,
+                body: read(body).boxed_local(),
+            });
+        }
+    }
+
+    let parsed = ipld::Cid::parse(&cid)?;
+
+    let resp = fetch(blob_url(pds, did, &cid), RequestMode::Cors, &[]).await?;
+
+    if resp.status() != 200 {
+        let body = JsFuture::from(resp.text()?).await?.as_string();
+        let body = serde_json::from_str(&body.unwrap_or_default()).unwrap_or_default();
+
+        return Err(blob_error(cid, resp.status(), &body));
+    }
+
+    let mime_type = resp.headers().get("Content-Type")?;
+    let size = content_length(&resp)?;
+
+    let body = match resp.body() {
+        Some(b) => verify::blob_stream(parsed, read(b)),
+        None => return Err(Error::XRPCError(format!("blob {}: empty body", cid))),
+    };
+
+    // the cache reads its own copy of the stream, only when it will actually keep it: an unread
+    // copy would be buffered forever
+    let body = match size {
+        Some(size) if size <= config::get().cache_size => {
+            let branches = readable(body).tee();
+
+            let cached = Response::new_with_opt_readable_stream_and_init(
+                Some(&branches.get(1).unchecked_into()),
+                &blob_init(mime_type.clone())?,
+            )?;
+
+            wasm_bindgen_futures::spawn_local(async move {
+                if let Err(e) = cache::store(&key, &cached, size).await {
+                    log::debug!("can't cache blob {}: {:?}", key, e);
+                }
+            });
+
+            read(branches.get(0).unchecked_into()).boxed_local()
+        }
+        _ => body.boxed_local(),
+    };
+
+    Ok(Streamed {
+        mime_type,
+        size,
+        body,
+    })
+}
+
+/// blob_range returns range of the blob with the given CID in did's repo as the PDS serves it, or
+/// None if the PDS sent the whole blob instead, or didn't say which range it sent.
+pub async fn blob_range(
+    pds: String,
+    did: String,
+    cid: String,
+    range: Range,
+) -> Result<Option<Partial<LocalBoxStream<'static, Result<Vec<u8>, Error>>>>, Error> {
+    let resp = fetch(
+        blob_url(pds, did, &cid),
+        RequestMode::Cors,
+        &[("Range", &range.to_string())],
+    )
+    .await?;
+
+    let status = resp.status();
+    // PDSes have to expose Content-Range for it to be read here
+    let content_range = resp.headers().get("Content-Range")?;
+
+    match status {
+        206 if content_range.is_none() => Ok(None),
+        206 | 416 => Ok(Some(Partial {
+            status,
+            content_range,
+            mime_type: resp.headers().get("Content-Type")?,
+            body: match resp.body() {
+                Some(b) => read(b).boxed_local(),
+                None => futures::stream::empty().boxed_local(),
+            },
+        })),
+        200 => Ok(None),
+        _ => {
+            let body = JsFuture::from(resp.text()?).await?.as_string();
+            let body = serde_json::from_str(&body.unwrap_or_default()).unwrap_or_default();
+
+            Err(blob_error(cid, status, &body))
+        }
+    }
+}
+
+/// cached returns the blob with the given CID if it's in the cache.
+pub async fn cached(cid: &str) -> Option<GetData> {
+    cached_blob(&cache::key(BLOB_KIND, cid)).await.ok()?
+}
+
+/// read returns the chunks of body as they're read.
+fn read(body: ReadableStream) -> impl Stream<Item = Result<Vec<u8>, Error>> {
+    wasm_streams::ReadableStream::from_raw(body.unchecked_into())
+        .into_stream()
+        .map(|chunk| Ok(Uint8Array::new(&chunk?).to_vec()))
+}
+
+/// readable returns stream as a JavaScript stream, for the browser to read.
+pub fn readable(stream: impl Stream<Item = Result<Vec<u8>, Error>> + 'static) -> ReadableStream {
+    let stream = stream.map(|chunk| match chunk {
+        Ok(data) => Ok(JsValue::from(Uint8Array::from(&data[..]))),
+        Err(e) => Err(JsValue::from(e.to_string())),
+    });
+
+    wasm_streams::ReadableStream::from_stream(stream)
+        .into_raw()
+        .unchecked_into()
+}
+
+fn content_length(resp: &Response) -> Result<Option<u64>, Error> {
+    Ok(resp
+        .headers()
+        .get("Content-Length")?
+        .and_then(|l| l.parse().ok()))
+}
+
+/// get_blob returns the content of the blob with the given CID in did's repo, after checking that
+/// it actually hashes to that CID.
+pub async fn get_blob(
+    host: &impl Host,
+    pds: String,
+    did: String,
+    cid: String,
+) -> Result<host::Response, Error> {
+    let resp = host.fetch(&blob_url(pds, did, &cid), &[]).await?;
+
+    if resp.status != 200 {
+        return Err(blob_error(cid, resp.status, &resp.json()));
     }
 
-    let bu = Url::new(&base).unwrap();
+    verify::blob(&cid, &resp.body)?;
 
-    args.into_iter()
-        .for_each(|(k, v)| bu.search_params().set(k, v));
+    Ok(resp)
+}
+
+pub(crate) fn blob_error(cid: String, status: u16, body: &serde_json::Value) -> Error {
+    match body.get("error").and_then(|e| e.as_str()) {
+        Some("BlobNotFound") => Error::NoBlobFound(cid),
+        Some(e) => Error::XRPCError(format!("blob {}: {}", cid, e)),
+        None => Error::XRPCError(format!("blob {}: status {}", cid, status)),
+    }
+}
 
-    bu.href()
+fn blob_init(mime_type: Option<String>) -> Result<ResponseInit, JsValue> {
+    let ri = ResponseInit::new();
+    ri.set_status(200);
+
+    let h = Headers::new()?;
+    h.set("Content-Type", &mime_type.unwrap_or_default())?;
+    ri.set_headers(&h);
+
+    Ok(ri)
 }
 
-pub fn parse_at_url(u: String) -> Option<Result<ATURL, Error>> {
-    let jsu = Url::new(&u).unwrap();
+pub(crate) fn blob_url(pds: String, did: String, cid: &str) -> String {
+    let args = [
+        ("did".to_string(), did),
+        ("cid".to_string(), cid.to_string()),
+    ];
 
-    jsu.pathname()
-        .strip_prefix("/at/")
-        .map(|e| TryFrom::try_from(e.to_string()).map_err(|e| Error::MalformedATURL(e)))
+    url(pds_url(pds, "com.atproto.sync.getBlob".to_string()), &args)
 }
 
-pub async fn solve_did(handle: String) -> Result<String, Error> {
-    let params = [("handle".to_string(), handle.clone())];
+async fn cached_blob(key: &str) -> Result<Option<GetData>, Error> {
+    let resp = match cache::lookup(key).await {
+        Some(r) => r,
+        None => return Ok(None),
+    };
+
+    Ok(Some(GetData {
+        value: JsFuture::from(resp.array_buffer()?).await?,
+        mime_type: resp.headers().get("Content-Type")?,
+        status: resp.status(),
+    }))
+}
+
+/// check_blob makes sure data hashes to cid before caching it.
+async fn check_blob(cid: &str, key: &str, data: GetData) -> Result<GetData, Error> {
+    let buffer = Uint8Array::new(&data.value);
+    verify::blob(cid, &buffer.to_vec())?;
+
+    if let Err(e) = store_blob(key, &buffer, data.mime_type.clone()).await {
+        log::debug!("can't cache blob {}: {:?}", cid, e);
+    }
+
+    Ok(data)
+}
+
+async fn store_blob(
+    key: &str,
+    buffer: &Uint8Array,
+    mime_type: Option<String>,
+) -> Result<(), JsValue> {
+    let resp = Response::new_with_opt_js_u8_array_and_init(Some(buffer), &blob_init(mime_type)?)?;
+
+    cache::store(key, &resp, buffer.length() as u64).await
+}
+
+/// car returns the CAR file holding the record at collection/rkey in did's repo, along with
+/// the commit and MST nodes proving its inclusion.
+pub async fn car(
+    host: &impl Host,
+    pds: String,
+    did: String,
+    collection: String,
+    rkey: String,
+) -> Result<Vec<u8>, Error> {
+    let args = [
+        ("did".to_string(), did.clone()),
+        ("collection".to_string(), collection.clone()),
+        ("rkey".to_string(), rkey.clone()),
+    ];
+
     let u = url(
-        bsky_url("com.atproto.identity.resolveHandle".to_string()),
-        &params,
+        pds_url(pds, "com.atproto.sync.getRecord".to_string()),
+        &args,
     );
 
-    let data = get(u, false).await?;
+    let resp = host.fetch(&u, &[]).await?;
+
+    if resp.status == 200 {
+        return Ok(resp.body);
+    }
 
-    let resp: HashMap<String, String> = serde_wasm_bindgen::from_value(data.value)?;
+    let uri = format!("at://{}/{}/{}", did, collection, rkey);
 
-    if let Some(did) = resp.get("did") {
-        Ok(did.clone())
-    } else {
-        Err(Error::NoDIDFound(handle))
+    match resp.json().get("error").and_then(|e| e.as_str()) {
+        Some("RecordNotFound") => Err(Error::NoRecordFound(uri)),
+        Some(e) => Err(Error::XRPCError(format!("{}: {}", uri, e))),
+        None => Err(Error::XRPCError(format!("{}: status {}", uri, resp.status))),
     }
 }
 
-pub async fn solve_pds(did: String) -> Result<String, Error> {
-    let data = get(plc_url(did.clone()), false).await?;
+/// Record is the value of a record along with its CID.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Record {
+    pub cid: String,
+    pub value: serde_json::Value,
+}
 
-    let resp: serde_json::Value = serde_wasm_bindgen::from_value(data.value)?;
+/// record returns the value of the record at collection/rkey in did's repo.
+/// Cached records are returned right away and refreshed in the background, so that the next
+/// request for them sees the latest version.
+pub async fn record(
+    pds: String,
+    did: String,
+    collection: String,
+    rkey: String,
+) -> Result<serde_json::Value, Error> {
+    let key = cache::key(RECORD_KIND, &format!("{}/{}/{}", did, collection, rkey));
 
-    Ok(
-        match resp
-            .get("service")
-            .and_then(|e| e.get(0))
-            .and_then(|e| e.get("serviceEndpoint"))
-            .and_then(|e| e.as_str())
-        {
-            Some(s) => s.to_string(),
-            None => return Err(Error::NoPDSFound(did)),
-        },
-    )
+    if let Some(cached) = cached_record(&key).await {
+        log::debug!("record {} served from cache", key);
+
+        wasm_bindgen_futures::spawn_local(revalidate(pds, did, collection, rkey, key, cached.cid));
+
+        return Ok(cached.value);
+    }
+
+    let record = fetch_record(&Worker, pds, did, collection, rkey).await?;
+
+    store_record(&key, &record).await;
+
+    Ok(record.value)
 }
 
-pub async fn data(
+async fn revalidate(
     pds: String,
     did: String,
     collection: String,
-    blob: bool,
-) -> Result<GetData, Error> {
-    let (method, args) = match blob {
-        true => (
-            "com.atproto.sync.getBlob",
-            &[
-                ("did".to_string(), did.clone()),
-                ("cid".to_string(), collection),
-            ],
-        ),
-        false => (
-            "com.atproto.repo.listRecords",
-            &[
-                ("repo".to_string(), did.clone()),
-                ("collection".to_string(), collection),
-            ],
-        ),
+    rkey: String,
+    key: String,
+    cid: String,
+) {
+    match fetch_record(&Worker, pds, did, collection, rkey).await {
+        Ok(record) if record.cid != cid => {
+            log::debug!("record {} changed, now at {}", key, record.cid);
+            store_record(&key, &record).await;
+        }
+        Ok(_) => {}
+        Err(Error::NoRecordFound(_)) => {
+            if let Err(e) = cache::remove(&key).await {
+                log::debug!("can't remove {} from cache: {:?}", key, e);
+            }
+        }
+        // most likely offline, keep serving what's in the cache
+        Err(e) => log::debug!("can't revalidate {}: {:?}", key, e),
+    }
+}
+
+async fn cached_record(key: &str) -> Option<Record> {
+    let resp = cache::lookup(key).await?;
+    let text = JsFuture::from(resp.text().ok()?).await.ok()?.as_string()?;
+
+    serde_json::from_str(&text).ok()
+}
+
+async fn store_record(key: &str, record: &Record) {
+    let body = match serde_json::to_string(record) {
+        Ok(b) => b,
+        Err(e) => return log::debug!("can't serialize record {}: {}", key, e),
     };
 
-    let u = url(pds_url(pds, method.to_string()), args);
+    let res = match Response::new_with_opt_str(Some(&body)) {
+        Ok(resp) => cache::store(key, &resp, body.len() as u64).await,
+        Err(e) => Err(e),
+    };
 
-    Ok(get(u, blob).await?)
+    if let Err(e) = res {
+        log::debug!("can't cache record {}: {:?}", key, e);
+    }
 }
 
-pub async fn webpage(
-    raw_data: JsValue,
-    key: String,
+/// fetch_record returns the record at collection/rkey in did's repo, straight from its PDS.
+pub async fn fetch_record(
+    host: &impl Host,
     pds: String,
     did: String,
-) -> Result<Webpage, Error> {
-    let resp: serde_json::Value = serde_wasm_bindgen::from_value(raw_data)?;
-
-    let arr = resp.get("records").and_then(|e| e.as_array()).unwrap();
-
-    let mut value = None;
-    for e in arr {
-        let e_cloned = e.clone();
-        let uri = e_cloned.get("uri").unwrap();
-        let uri = uri.as_str().unwrap();
-        if uri.ends_with(&key) {
-            value = Some(e);
-            break;
-        }
+    collection: String,
+    rkey: String,
+) -> Result<Record, Error> {
+    if host.config().verify {
+        return verify::record(host, pds, did, collection, rkey).await;
     }
 
-    let page = value // grab only the first record ever
-        .and_then(|e| e.get("value"))
-        .unwrap(); // from now on we have the real record
+    let args = [
+        ("repo".to_string(), did.clone()),
+        ("collection".to_string(), collection.clone()),
+        ("rkey".to_string(), rkey.clone()),
+    ];
 
-    log::debug!("{:?}", page.as_str());
-    let content_id = page
-        .get("content")
-        .unwrap()
-        .get("ref")
-        .unwrap()
-        .get("$link")
-        .unwrap()
-        .as_str()
-        .unwrap()
-        .to_string();
-
-    log::debug!("Page content blob ref: {}", content_id.clone());
+    let u = url(
+        pds_url(pds, "com.atproto.repo.getRecord".to_string()),
+        &args,
+    );
 
-    let res = data(pds, did, content_id.clone(), true).await?;
+    let resp = host.fetch(&u, &[("Accept", "application/json")]).await?;
+    let body = resp.json();
 
-    let buffer = Uint8Array::new(&res.value);
+    let uri = format!("at://{}/{}/{}", did, collection, rkey);
 
-    let content = String::from_utf8(buffer.to_vec()).unwrap();
+    if resp.status == 200 {
+        return match (body.get("value"), body.get("cid").and_then(|c| c.as_str())) {
+            (Some(v), Some(cid)) => Ok(Record {
+                cid: cid.to_string(),
+                value: v.clone(),
+            }),
+            _ => Err(Error::MalformedRecord(uri)),
+        };
+    }
 
-    Ok(Webpage { content })
+    // only the PDS saying so makes a record missing, a PDS failing can't be told apart otherwise
+    match (resp.status, body.get("error").and_then(|e| e.as_str())) {
+        (_, Some("RecordNotFound")) | (404, _) => Err(Error::NoRecordFound(uri)),
+        (_, Some(e)) => Err(Error::XRPCError(format!("{}: {}", uri, e))),
+        (status, None) => Err(Error::XRPCError(format!("{}: status {}", uri, status))),
+    }
 }
 
 #[derive(Clone)]
 pub struct GetData {
     pub value: JsValue,
     pub mime_type: Option<String>,
+    pub status: u16,
 }
 
 pub async fn get(url: String, blob: bool) -> Result<GetData, JsValue> {
@@ -224,11 +586,20 @@ pub async fn get(url: String, blob: bool) -> Result<GetData, JsValue> {
     Ok(GetData {
         value: json,
         mime_type: ct,
+        status: resp.status(),
     })
 }
 
 #[wasm_bindgen]
 pub async fn get_raw_worker(url: String, req_mode: RequestMode) -> Result<Response, JsValue> {
+    fetch(url, req_mode, &[("Accept", "application/json")]).await
+}
+
+pub(crate) async fn fetch(
+    url: String,
+    req_mode: RequestMode,
+    headers: &[(&str, &str)],
+) -> Result<Response, JsValue> {
     use wasm_bindgen::JsCast;
 
     let worker = js_sys::global().dyn_into::<WorkerGlobalScope>().unwrap();
@@ -239,7 +610,9 @@ pub async fn get_raw_worker(url: String, req_mode: RequestMode) -> Result<Respon
 
     let request = Request::new_with_str_and_init(&url, &opts)?;
 
-    request.headers().set("Accept", "application/json")?;
+    for (k, v) in headers {
+        request.headers().set(k, v)?;
+    }
 
     let resp_value = JsFuture::from(worker.fetch_with_request(&request)).await?;
 
@@ -0,0 +1,216 @@
+use crate::config;
+use serde::{Deserialize, Serialize};
+use std::{cell::RefCell, collections::HashMap};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Cache, Request, Response, WorkerGlobalScope};
+
+/// Cache keys must be URLs, this is the made-up origin all of atpage's keys live under.
+const KEY_BASE: &str = "https://atpage.invalid";
+
+/// key returns the URL under which the entry named key of kind is stored.
+pub fn key(kind: &str, key: &str) -> String {
+    format!("{}/{}/{}", KEY_BASE, kind, key)
+}
+
+/// open opens the Cache API cache called name, creating it if needed.
+pub async fn open(name: &str) -> Result<Cache, JsValue> {
+    let worker = js_sys::global().dyn_into::<WorkerGlobalScope>().unwrap();
+
+    let cache = JsFuture::from(worker.caches()?.open(name)).await?;
+
+    cache.dyn_into()
+}
+
+/// get returns the response stored at key in cache, if any.
+pub async fn get(cache: &Cache, key: &str) -> Result<Option<Response>, JsValue> {
+    let resp = JsFuture::from(cache.match_with_str(key)).await?;
+
+    match resp.is_undefined() {
+        true => Ok(None),
+        false => Ok(Some(resp.dyn_into()?)),
+    }
+}
+
+/// put stores resp at key in cache, replacing what was there.
+pub async fn put(cache: &Cache, key: &str, resp: &Response) -> Result<(), JsValue> {
+    JsFuture::from(cache.put_with_str(key, resp)).await?;
+
+    Ok(())
+}
+
+/// Content cache holding records and blobs, bounded by Config::cache_size.
+const CONTENT_CACHE: &str = "atpage-content";
+const INDEX_KIND: &str = "index";
+
+thread_local! {
+    static INDEX: RefCell<Option<Index>> = const { RefCell::new(None) };
+}
+
+/// Index tracks the size and last use of every entry in the content cache, so that the least
+/// recently used ones can be evicted once it grows past its size limit.
+#[derive(Serialize, Deserialize, Default, Clone)]
+struct Index {
+    entries: HashMap<String, Entry>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Entry {
+    size: u64,
+    used: f64,
+}
+
+impl Index {
+    fn size(&self) -> u64 {
+        self.entries.values().map(|e| e.size).sum()
+    }
+}
+
+/// load loads the index into memory, unless it's there already.
+///
+/// Entries of the content cache the persisted index doesn't know of are deleted, and entries of
+/// the index missing from the content cache forgotten, so that the index accounts for every entry.
+async fn load(cache: &Cache) {
+    if INDEX.with_borrow(|i| i.is_some()) {
+        return;
+    }
+
+    let mut idx = load_index(cache).await.unwrap_or_default();
+
+    let keys = match stored_keys(cache).await {
+        Ok(keys) => keys,
+        Err(e) => {
+            log::debug!("can't list cache entries: {:?}", e);
+            vec![]
+        }
+    };
+
+    idx.entries.retain(|k, _| keys.contains(k));
+
+    // another call may have loaded the index while this one was waiting
+    let loaded = INDEX.with_borrow_mut(|i| match i {
+        Some(loaded) => loaded.clone(),
+        None => i.insert(idx).clone(),
+    });
+
+    let index_key = key(INDEX_KIND, INDEX_KIND);
+    for k in keys {
+        if k != index_key && !loaded.entries.contains_key(&k) {
+            log::debug!("deleting untracked {} from cache", k);
+            JsFuture::from(cache.delete_with_str(&k)).await.ok();
+        }
+    }
+}
+
+/// stored_keys returns the keys of every entry in cache.
+async fn stored_keys(cache: &Cache) -> Result<Vec<String>, JsValue> {
+    let reqs: js_sys::Array = JsFuture::from(cache.keys()).await?.dyn_into()?;
+
+    reqs.iter()
+        .map(|r| r.dyn_into::<Request>().map(|r| r.url()))
+        .collect()
+}
+
+/// update applies f to the index in memory.
+/// It never awaits in between reading and writing the index, so that concurrent updates can't
+/// overwrite each other.
+fn update<T>(f: impl FnOnce(&mut Index) -> T) -> T {
+    INDEX.with_borrow_mut(|i| f(i.get_or_insert_with(Index::default)))
+}
+
+async fn load_index(cache: &Cache) -> Option<Index> {
+    let resp = get(cache, &key(INDEX_KIND, INDEX_KIND)).await.ok()??;
+    let text = JsFuture::from(resp.text().ok()?).await.ok()?.as_string()?;
+
+    serde_json::from_str(&text).ok()
+}
+
+/// save_index persists the index as it is in memory.
+async fn save_index(cache: &Cache) -> Result<(), JsValue> {
+    let body =
+        update(|idx| serde_json::to_string(idx)).map_err(|e| JsValue::from(e.to_string()))?;
+
+    put(
+        cache,
+        &key(INDEX_KIND, INDEX_KIND),
+        &Response::new_with_opt_str(Some(&body))?,
+    )
+    .await
+}
+
+/// lookup returns the content stored at key, marking it as recently used.
+pub async fn lookup(key: &str) -> Option<Response> {
+    let cache = open(CONTENT_CACHE).await.ok()?;
+    load(&cache).await;
+
+    let resp = get(&cache, key).await.ok()??;
+
+    // the index is only persisted on the next store(), losing a few last-use times on restart is fine
+    update(|idx| {
+        if let Some(e) = idx.entries.get_mut(key) {
+            e.used = js_sys::Date::now();
+        }
+    });
+
+    Some(resp)
+}
+
+/// store saves resp, whose body is size bytes long, at key, then evicts the least recently used
+/// entries until the content cache fits in its size limit.
+pub async fn store(key: &str, resp: &Response, size: u64) -> Result<(), JsValue> {
+    let limit = config::get().cache_size;
+    if size > limit {
+        return Ok(());
+    }
+
+    let cache = open(CONTENT_CACHE).await?;
+    load(&cache).await;
+
+    // tracked before it's stored, so that a concurrent load() doesn't delete it as untracked
+    let evicted = update(|idx| {
+        idx.entries.insert(
+            key.to_string(),
+            Entry {
+                size,
+                used: js_sys::Date::now(),
+            },
+        );
+
+        let mut evicted = vec![];
+        while idx.size() > limit {
+            let oldest = match idx
+                .entries
+                .iter()
+                .min_by(|a, b| a.1.used.total_cmp(&b.1.used))
+            {
+                Some((k, _)) => k.clone(),
+                None => break,
+            };
+
+            idx.entries.remove(&oldest);
+            evicted.push(oldest);
+        }
+
+        evicted
+    });
+
+    put(&cache, key, resp).await?;
+
+    for k in evicted {
+        log::debug!("evicting {} from cache", k);
+        JsFuture::from(cache.delete_with_str(&k)).await?;
+    }
+
+    save_index(&cache).await
+}
+
+/// remove deletes the content stored at key.
+pub async fn remove(key: &str) -> Result<(), JsValue> {
+    let cache = open(CONTENT_CACHE).await?;
+    load(&cache).await;
+
+    update(|idx| idx.entries.remove(key));
+    JsFuture::from(cache.delete_with_str(key)).await?;
+
+    save_index(&cache).await
+}
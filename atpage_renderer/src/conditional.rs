@@ -0,0 +1,231 @@
+use crate::range::{self, Range};
+use web_sys::Request;
+
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+const REVALIDATE: &str = "no-cache";
+
+/// Conditions are the request headers that change what's served for the same resource.
+#[derive(Clone, Default, Debug)]
+pub struct Conditions {
+    pub range: Option<Range>,
+    if_none_match: Option<String>,
+}
+
+impl Conditions {
+    /// new returns the conditions set by the given Range and If-None-Match headers.
+    pub fn new(range: Option<&str>, if_none_match: Option<&str>) -> Self {
+        Conditions {
+            range: range.and_then(range::parse),
+            if_none_match: if_none_match.map(|h| h.to_string()),
+        }
+    }
+
+    pub fn from_request(req: &Request) -> Self {
+        let header = |name| req.headers().get(name).ok().flatten();
+
+        Self::new(
+            header("Range").as_deref(),
+            header("If-None-Match").as_deref(),
+        )
+    }
+
+    /// fresh returns true if the client already holds the version of the resource tagged etag.
+    pub fn fresh(&self, etag: &str) -> bool {
+        let header = match &self.if_none_match {
+            Some(h) => h,
+            None => return false,
+        };
+
+        // If-None-Match uses weak comparison
+        header.split(',').map(|t| t.trim()).any(|t| {
+            t == "*" || t.trim_start_matches("W/").trim_matches('"') == etag.trim_matches('"')
+        })
+    }
+}
+
+/// Validators identify the version of a resource served.
+pub struct Validators {
+    etag: String,
+    immutable: bool,
+    last_modified: Option<String>,
+}
+
+impl Validators {
+    /// content returns the validators of content-addressed data, which never changes.
+    pub fn content(cid: &str) -> Self {
+        Validators {
+            etag: format!("\"{}\"", cid),
+            immutable: true,
+            last_modified: None,
+        }
+    }
+
+    /// page returns the validators of a page whose content is stored at cid, and that was last
+    /// updated at updated_at, an RFC 3339 timestamp.
+    /// Pages can change under the same path, so they must be revalidated each time.
+    pub fn page(cid: &str, updated_at: Option<&str>) -> Self {
+        Validators {
+            etag: format!("\"{}\"", cid),
+            immutable: false,
+            last_modified: updated_at.and_then(http_date),
+        }
+    }
+
+    pub fn etag(&self) -> &str {
+        &self.etag
+    }
+
+    /// headers returns the validators as response headers.
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        let mut h = vec![
+            ("ETag", self.etag.clone()),
+            (
+                "Cache-Control",
+                match self.immutable {
+                    true => IMMUTABLE,
+                    false => REVALIDATE,
+                }
+                .to_string(),
+            ),
+        ];
+
+        if let Some(lm) = &self.last_modified {
+            h.push(("Last-Modified", lm.clone()));
+        }
+
+        h
+    }
+}
+
+const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// http_date converts an RFC 3339 timestamp to the HTTP date format, like
+/// "Sun, 06 Nov 1994 08:49:37 GMT".
+pub fn http_date(rfc3339: &str) -> Option<String> {
+    let num = |range: std::ops::Range<usize>| rfc3339.get(range)?.parse::<i64>().ok();
+
+    let (year, month, day) = (num(0..4)?, num(5..7)?, num(8..10)?);
+    let (hour, minute, second) = (num(11..13)?, num(14..16)?, num(17..19)?);
+
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // the offset follows the optional fractional seconds
+    let zone = rfc3339
+        .get(19..)?
+        .trim_start_matches(|c: char| c == '.' || c.is_ascii_digit());
+    let offset = match zone {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match zone.get(..1)? {
+                "+" => 1,
+                "-" => -1,
+                _ => return None,
+            };
+            let (h, m) = (
+                zone.get(1..3)?.parse::<i64>().ok()?,
+                zone.get(4..6)?.parse::<i64>().ok()?,
+            );
+            sign * (h * 3600 + m * 60)
+        }
+    };
+
+    let secs =
+        days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+    let (days, secs) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    let (year, month, day) = civil_from_days(days);
+
+    Some(format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        DAYS[days.rem_euclid(7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    ))
+}
+
+/// days_from_civil returns the number of days between the epoch and the given date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146097 + doe - 719468
+}
+
+/// civil_from_days is the inverse of days_from_civil.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+
+    (yoe + era * 400 + if month <= 2 { 1 } else { 0 }, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_utc_dates() {
+        assert_eq!(
+            http_date("1994-11-06T08:49:37Z").as_deref(),
+            Some("Sun, 06 Nov 1994 08:49:37 GMT")
+        );
+    }
+
+    #[test]
+    fn converts_offset_dates() {
+        assert_eq!(
+            http_date("2000-03-01T00:30:00.123-01:00").as_deref(),
+            Some("Wed, 01 Mar 2000 01:30:00 GMT")
+        );
+    }
+
+    #[test]
+    fn refuses_malformed_dates() {
+        assert_eq!(http_date("yesterday"), None);
+    }
+
+    #[test]
+    fn matches_weak_etags() {
+        let validators = Validators::content("bafkrei");
+
+        assert!(Conditions::new(None, Some("\"other\", W/\"bafkrei\"")).fresh(validators.etag()));
+    }
+
+    #[test]
+    fn misses_other_etags() {
+        let validators = Validators::content("bafkrei");
+
+        assert!(!Conditions::new(None, Some("\"other\"")).fresh(validators.etag()));
+    }
+
+    #[test]
+    fn revalidates_pages() {
+        let validators = Validators::page("bafkrei", Some("2024-11-06T08:49:37.000+01:00"));
+
+        assert_eq!(
+            validators.headers(),
+            vec![
+                ("ETag", "\"bafkrei\"".to_string()),
+                ("Cache-Control", REVALIDATE.to_string()),
+                ("Last-Modified", "Wed, 06 Nov 2024 07:49:37 GMT".to_string()),
+            ]
+        );
+    }
+}
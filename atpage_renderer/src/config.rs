@@ -0,0 +1,80 @@
+use serde::Deserialize;
+use std::sync::OnceLock;
+use wasm_bindgen::prelude::*;
+
+const IDENTITY_TTL: u64 = 60 * 60;
+const CACHE_SIZE: u64 = 100 * 1024 * 1024;
+const DOH_RESOLVER: &str = "https://cloudflare-dns.com/dns-query";
+const PLC_DIRECTORY: &str = "https://plc.directory";
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Config holds the renderer settings, passed by the service worker to configure().
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Config {
+    /// Handle or DID of the site served at plain paths, like /blog/post.html.
+    /// When unset, only /at/ URLs are served, unless domains is set.
+    pub site: Option<String>,
+
+    /// When site is unset, serve the site claiming the worker's domain at plain paths, as found
+    /// in the domain's _atpage TXT record or /.well-known/atpage.json.
+    pub domains: bool,
+
+    /// Handles or DIDs whose pages are served as they are. Pages of any other repo are stripped of
+    /// scripts and forms, and served with a policy that forbids running code.
+    /// The site is always trusted.
+    pub trusted: Vec<String>,
+
+    /// Verify that every record is signed by its repo and included in its MST, refusing
+    /// to render anything that isn't.
+    pub verify: bool,
+
+    /// DNS-over-HTTPS endpoint speaking the JSON API, used to look up handles' _atproto TXT records.
+    pub doh_resolver: String,
+
+    /// XRPC service whose resolveHandle is used when neither DNS nor HTTPS resolve a handle.
+    pub handle_resolver: Option<String>,
+
+    /// PLC directory did:plc documents are fetched from.
+    pub plc_directory: String,
+
+    /// How long resolved handles and DIDs are cached for, in seconds.
+    pub identity_ttl: u64,
+
+    /// Maximum size of the records and blobs kept for offline use, in bytes.
+    pub cache_size: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            site: None,
+            domains: false,
+            trusted: vec![],
+            verify: false,
+            doh_resolver: DOH_RESOLVER.to_string(),
+            handle_resolver: None,
+            plc_directory: PLC_DIRECTORY.to_string(),
+            identity_ttl: IDENTITY_TTL,
+            cache_size: CACHE_SIZE,
+        }
+    }
+}
+
+/// configure sets the renderer configuration, must be called before the first resolve().
+#[wasm_bindgen]
+pub fn configure(config: JsValue) -> Result<(), JsValue> {
+    let config: Config = serde_wasm_bindgen::from_value(config)?;
+
+    log::debug!("renderer configuration: {:?}", config);
+
+    CONFIG
+        .set(config)
+        .map_err(|_| JsValue::from("renderer already configured"))
+}
+
+/// get returns the renderer configuration, or the default one if configure() wasn't called.
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
@@ -0,0 +1,123 @@
+use crate::{atproto::Error, domain, host::Host};
+use serde::Deserialize;
+
+const PDS_SERVICE_ID: &str = "#atproto_pds";
+const PDS_SERVICE_TYPE: &str = "AtprotoPersonalDataServer";
+const SIGNING_KEY_ID: &str = "#atproto";
+const HANDLE_PREFIX: &str = "at://";
+
+/// Document is the subset of a DID document atpage cares about.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Document {
+    pub id: String,
+    #[serde(default)]
+    pub also_known_as: Vec<String>,
+    #[serde(default)]
+    pub verification_method: Vec<VerificationMethod>,
+    #[serde(default)]
+    pub service: Vec<Service>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct VerificationMethod {
+    pub id: String,
+    pub public_key_multibase: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Service {
+    pub id: String,
+    pub r#type: String,
+    pub service_endpoint: serde_json::Value,
+}
+
+/// resolve fetches the DID document of did, from the PLC directory for did:plc or from the
+/// domain's well-known did.json for did:web.
+/// Only DIDs that the directory or the domain doesn't know are missing, any other failure is
+/// reported as such.
+pub async fn resolve(host: &impl Host, did: String) -> Result<Document, Error> {
+    let u = document_url(host, &did)?;
+
+    let resp = host.fetch(&u, &[("Accept", "application/json")]).await?;
+
+    // the PLC directory answers 410 for deactivated DIDs
+    match resp.status {
+        200 => {}
+        404 | 410 => return Err(Error::NoDIDFound(did)),
+        status => {
+            return Err(Error::FetchError(format!(
+                "{} answered with status {}",
+                u, status
+            )))
+        }
+    }
+
+    let doc: Document = serde_json::from_slice(&resp.body)
+        .map_err(|e| Error::FetchError(format!("malformed DID document for {}: {}", did, e)))?;
+
+    if doc.id != did {
+        return Err(Error::NoDIDFound(did));
+    }
+
+    Ok(doc)
+}
+
+fn document_url(host: &impl Host, did: &str) -> Result<String, Error> {
+    if did.starts_with("did:plc:") {
+        let plc_directory = &host.config().plc_directory;
+        return Ok(format!("{}/{}", plc_directory.trim_end_matches('/'), did));
+    }
+
+    match did.strip_prefix("did:web:") {
+        // atproto only allows hostname-level did:web, ports are only meant for development
+        Some(domain) if domain::public(domain) => Ok(host.web_url(domain, "/.well-known/did.json")),
+        _ => Err(Error::UnsupportedDID(did.to_string())),
+    }
+}
+
+/// matches returns true if both ids refer to the same entry in the document, since ids
+/// can either be relative ("#atproto") or fully qualified ("did:plc:...#atproto").
+fn matches(doc: &Document, id: &str, wanted: &str) -> bool {
+    id == wanted || id == format!("{}{}", doc.id, wanted)
+}
+
+impl Document {
+    /// pds returns the endpoint of the #atproto_pds service.
+    pub fn pds(&self) -> Result<String, Error> {
+        self.service
+            .iter()
+            .find(|s| matches(self, &s.id, PDS_SERVICE_ID) && s.r#type == PDS_SERVICE_TYPE)
+            .and_then(|s| s.service_endpoint.as_str())
+            .map(|s| s.to_string())
+            .ok_or(Error::NoPDSFound(self.id.clone()))
+    }
+
+    /// signing_key returns the multibase-encoded #atproto repo signing key.
+    pub fn signing_key(&self) -> Option<&str> {
+        self.verification_method
+            .iter()
+            .find(|m| matches(self, &m.id, SIGNING_KEY_ID))
+            .and_then(|m| m.public_key_multibase.as_deref())
+    }
+
+    /// handle returns the first handle listed in alsoKnownAs.
+    pub fn handle(&self) -> Option<&str> {
+        self.also_known_as
+            .iter()
+            .find_map(|h| h.strip_prefix(HANDLE_PREFIX))
+    }
+
+    /// check_handle errors if handle isn't the handle the document claims.
+    pub fn check_handle(&self, handle: &str) -> Result<(), Error> {
+        match self.handle() {
+            Some(h) if h.eq_ignore_ascii_case(handle) => Ok(()),
+            _ => Err(Error::HandleMismatch(format!(
+                "{} doesn't claim handle {}",
+                self.id, handle
+            ))),
+        }
+    }
+}
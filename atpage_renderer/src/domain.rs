@@ -0,0 +1,130 @@
+use crate::{
+    atproto::{self, Error},
+    handle,
+    host::{Host, Worker},
+    identity::{self, Identity},
+    records,
+};
+use shared::atproto::{SITE_COLLECTION, SITE_RKEY};
+use std::{cell::RefCell, collections::HashMap};
+
+const DNS_PREFIX: &str = "_atpage.";
+const WELL_KNOWN_PATH: &str = "/.well-known/atpage.json";
+/// Top-level domains atproto doesn't allow in handles, as they don't resolve on the internet.
+const RESERVED_TLDS: [&str; 8] = [
+    "alt",
+    "arpa",
+    "example",
+    "internal",
+    "invalid",
+    "local",
+    "localhost",
+    "onion",
+];
+
+thread_local! {
+    static DOMAINS: RefCell<HashMap<String, Identity>> = RefCell::new(HashMap::new());
+}
+
+/// resolve returns the identity of the repo whose site is served at domain, from memory or by
+/// resolving it from scratch.
+pub async fn resolve(domain: &str) -> Result<Identity, Error> {
+    let key = domain.to_lowercase();
+
+    let cached = DOMAINS.with_borrow(|d| d.get(&key).cloned());
+    if let Some(id) = cached.filter(|id| !id.expired(&Worker)) {
+        return Ok(id);
+    }
+
+    let id = solve(&Worker, &key).await?;
+
+    DOMAINS.with_borrow_mut(|d| d.insert(key, id.clone()));
+
+    Ok(id)
+}
+
+/// solve returns the identity of the repo whose site is served at domain, looking at its
+/// _atpage TXT record through DNS-over-HTTPS first and at its /.well-known/atpage.json then.
+///
+/// The site record must list domain back, so that a domain can't serve a site that doesn't want
+/// to be served there.
+pub async fn solve(host: &impl Host, domain: &str) -> Result<Identity, Error> {
+    let domain = domain.to_lowercase();
+
+    if !public(&domain) {
+        return Err(Error::UnclaimedDomain(domain));
+    }
+
+    let did = match handle::txt_did(host, &format!("{}{}", DNS_PREFIX, domain)).await {
+        Ok(Some(did)) => Some(did),
+        Ok(None) => None,
+        Err(e) => {
+            log::debug!("DNS lookup of {} failed: {:?}", domain, e);
+            None
+        }
+    };
+
+    let did = match did {
+        Some(did) => Some(did),
+        None => match well_known(host, &domain).await {
+            Ok(did) => did,
+            Err(e) => {
+                log::debug!("HTTPS lookup of {} failed: {:?}", domain, e);
+                None
+            }
+        },
+    };
+
+    let did = match did {
+        Some(did) if did.starts_with("did:") => did,
+        _ => return Err(Error::UnclaimedDomain(domain)),
+    };
+
+    let id = identity::solve(host, &did).await?;
+
+    let site = atproto::fetch_record(
+        host,
+        id.pds.clone(),
+        id.did.clone(),
+        SITE_COLLECTION.to_string(),
+        SITE_RKEY.to_string(),
+    )
+    .await?;
+
+    match records::domains(&site.value).contains(&domain) {
+        true => Ok(id),
+        false => Err(Error::UnclaimedDomain(domain)),
+    }
+}
+
+/// public returns true if domain can be a host on the internet: a name with at least two labels,
+/// no port, not an IP address and not under a reserved top-level domain, which would all point
+/// lookups anywhere.
+/// Domains, handles and did:web hosts are all checked, as any request can make a host fetch them.
+pub(crate) fn public(domain: &str) -> bool {
+    let labels = domain.split('.').collect::<Vec<&str>>();
+    let tld = labels[labels.len() - 1];
+
+    labels.len() >= 2
+        && labels.iter().all(|l| {
+            !l.is_empty() && l.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+        // top-level domains are never all digits, IPv4 addresses are
+        && !tld.chars().all(|c| c.is_ascii_digit())
+        && !RESERVED_TLDS.contains(&tld.to_lowercase().as_str())
+}
+
+async fn well_known(host: &impl Host, domain: &str) -> Result<Option<String>, Error> {
+    let u = host.web_url(domain, WELL_KNOWN_PATH);
+
+    let resp = host.fetch(&u, &[("Accept", "application/json")]).await?;
+    if resp.status != 200 {
+        return Ok(None);
+    }
+
+    Ok(resp
+        .json()
+        .get("did")
+        .and_then(|d| d.as_str())
+        .map(|d| d.to_string()))
+}
@@ -0,0 +1,45 @@
+use crate::atproto::Error;
+
+/// status returns the HTTP status a failure to serve a request maps to.
+pub fn status(e: &Error) -> u16 {
+    match e {
+        Error::NoRecordFound(_)
+        | Error::NoAssetFound(_)
+        | Error::NoBlobFound(_)
+        | Error::NoDIDFound(_)
+        | Error::HandleMismatch(_)
+        | Error::UnclaimedDomain(_) => 404,
+        Error::MalformedATURL(_) | Error::UnsupportedDID(_) => 400,
+        // anything else means the PDS, or the identity infrastructure in front of it,
+        // either can't be reached or served something we can't use
+        _ => 502,
+    }
+}
+
+pub fn reason(status: u16) -> &'static str {
+    match status {
+        400 => "Bad Request",
+        404 => "Not Found",
+        _ => "Bad Gateway",
+    }
+}
+
+/// html renders e as an HTML error page, returning it along with the matching status.
+pub fn html(e: &Error) -> (u16, String) {
+    let status = status(e);
+
+    let title = format!("{} {}", status, reason(status));
+    let body = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n</head>\n<body>\n<h1>{title}</h1>\n<p>{}</p>\n</body>\n</html>\n",
+        escape(&e.to_string())
+    );
+
+    (status, body)
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,116 @@
+use crate::{
+    atproto::{self, Error},
+    domain,
+    host::Host,
+};
+
+const DNS_PREFIX: &str = "_atproto.";
+const DNS_TXT_TYPE: u64 = 16;
+const DID_PREFIX: &str = "did=";
+
+/// resolve returns the DID handle points to, looking at its _atproto TXT record through
+/// DNS-over-HTTPS first and at its /.well-known/atproto-did endpoint then.
+/// If configured, an XRPC resolveHandle endpoint is used as last resort.
+///
+/// The DID document must claim handle back, which is checked by the caller once it's resolved.
+pub async fn resolve(host: &impl Host, handle: String) -> Result<String, Error> {
+    let handle = handle.to_lowercase();
+
+    if !domain::public(&handle) {
+        return Err(Error::NoDIDFound(handle));
+    }
+
+    match dns(host, &handle).await {
+        Ok(Some(did)) => return Ok(did),
+        Ok(None) => {}
+        Err(e) => log::debug!("DNS resolution for {} failed: {:?}", handle, e),
+    }
+
+    match well_known(host, &handle).await {
+        Ok(Some(did)) => return Ok(did),
+        Ok(None) => {}
+        Err(e) => log::debug!("HTTPS resolution for {} failed: {:?}", handle, e),
+    }
+
+    if let Some(resolver) = &host.config().handle_resolver {
+        return xrpc(host, resolver.clone(), handle).await;
+    }
+
+    Err(Error::NoDIDFound(handle))
+}
+
+async fn dns(host: &impl Host, handle: &str) -> Result<Option<String>, Error> {
+    txt_did(host, &format!("{}{}", DNS_PREFIX, handle)).await
+}
+
+/// txt_did returns the DID the "did=" TXT record of name holds, looked up through
+/// DNS-over-HTTPS, if there's exactly one.
+pub(crate) async fn txt_did(host: &impl Host, name: &str) -> Result<Option<String>, Error> {
+    let args = [
+        ("name".to_string(), name.to_string()),
+        ("type".to_string(), "TXT".to_string()),
+    ];
+
+    let u = atproto::url(host.config().doh_resolver.clone(), &args);
+
+    let resp = host
+        .fetch(&u, &[("Accept", "application/dns-json")])
+        .await?;
+    if resp.status != 200 {
+        return Ok(None);
+    }
+
+    let resp: serde_json::Value = serde_json::from_slice(&resp.body)
+        .map_err(|e| Error::XRPCError(format!("malformed DNS-over-HTTPS answer: {}", e)))?;
+
+    let dids = resp
+        .get("Answer")
+        .and_then(|a| a.as_array())
+        .map(|a| {
+            a.iter()
+                .filter(|r| r.get("type").and_then(|t| t.as_u64()) == Some(DNS_TXT_TYPE))
+                .filter_map(|r| r.get("data").and_then(|d| d.as_str()))
+                .filter_map(|d| d.trim_matches('"').strip_prefix(DID_PREFIX))
+                .map(|d| d.to_string())
+                .collect::<Vec<String>>()
+        })
+        .unwrap_or_default();
+
+    // more than one DID means the name is ambiguous, and must not resolve
+    match dids.as_slice() {
+        [did] => Ok(Some(did.clone())),
+        _ => Ok(None),
+    }
+}
+
+async fn well_known(host: &impl Host, handle: &str) -> Result<Option<String>, Error> {
+    let u = host.web_url(handle, "/.well-known/atproto-did");
+
+    let resp = host.fetch(&u, &[("Accept", "text/plain")]).await?;
+
+    let body = resp.text();
+    let did = body.trim();
+
+    match resp.status == 200 && did.starts_with("did:") {
+        true => Ok(Some(did.to_string())),
+        false => Ok(None),
+    }
+}
+
+async fn xrpc(host: &impl Host, resolver: String, handle: String) -> Result<String, Error> {
+    let params = [("handle".to_string(), handle.clone())];
+    let u = atproto::url(
+        atproto::pds_url(resolver, "com.atproto.identity.resolveHandle".to_string()),
+        &params,
+    );
+
+    let resp = host
+        .fetch(&u, &[("Accept", "application/json")])
+        .await?
+        .json();
+
+    match resp.get("did").and_then(|d| d.as_str()) {
+        Some(did) => Ok(did.to_string()),
+        None => Err(Error::NoDIDFound(handle)),
+    }
+}
@@ -0,0 +1,448 @@
+use crate::{
+    atproto::{self, Error},
+    config::{self, Config},
+    domain,
+    identity::{self, Identity},
+    ipld,
+    range::Range,
+    verify,
+};
+use futures::{
+    stream::{BoxStream, LocalBoxStream},
+    Stream, StreamExt,
+};
+use js_sys::Uint8Array;
+use std::future::Future;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::RequestMode;
+
+/// Time after which connecting to a server fails.
+#[cfg(not(target_arch = "wasm32"))]
+const CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+/// Time after which a server that stopped sending anything is given up on.
+#[cfg(not(target_arch = "wasm32"))]
+const READ_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Response is what a host got back for a GET request.
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// header returns the value of the header called name, if any.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// json returns the body parsed as JSON, or null if it isn't.
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_slice(&self.body).unwrap_or_default()
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).to_string()
+    }
+}
+
+/// Blob is the content of a blob, checked against its CID, along with the MIME type the PDS
+/// served it as.
+#[derive(Clone)]
+pub struct Blob {
+    pub data: Vec<u8>,
+    pub mime_type: Option<String>,
+}
+
+/// Streamed is the content of a blob, read as it comes.
+pub struct Streamed<S> {
+    /// MIME type the PDS served the blob as.
+    pub mime_type: Option<String>,
+
+    /// Size of the blob, if known before reading it.
+    pub size: Option<u64>,
+
+    pub body: S,
+}
+
+/// Partial is a range of a blob as the PDS served it, which can't be checked against its CID.
+pub struct Partial<S> {
+    /// 206, or 416 if the range is past the end of the blob.
+    pub status: u16,
+    pub content_range: Option<String>,
+    pub mime_type: Option<String>,
+    pub body: S,
+}
+
+/// Host is what resolving and serving sites needs from the platform it runs on: the network,
+/// settings and a clock.
+/// Identities, records and blobs are fetched from scratch every time, hosts that keep them
+/// around override the methods fetching them.
+/// Worker is the service worker one, Native runs anywhere else.
+pub trait Host: Sized {
+    /// Stream the content of blobs is read through.
+    type BlobStream: Stream<Item = Result<Vec<u8>, Error>> + Unpin + 'static;
+
+    /// fetch sends a GET request for url with the given headers.
+    fn fetch(
+        &self,
+        url: &str,
+        headers: &[(&str, &str)],
+    ) -> impl Future<Output = Result<Response, Error>>;
+
+    fn config(&self) -> &Config;
+
+    /// now returns the current time, in milliseconds since the epoch.
+    fn now(&self) -> f64;
+
+    /// web_url returns the URL of path on the web server of domain, where well-known files are.
+    fn web_url(&self, domain: &str, path: &str) -> String {
+        format!("https://{}{}", domain, path)
+    }
+
+    /// identity returns the identity of the repo id, a handle or a DID, points to.
+    fn identity(&self, id: &str) -> impl Future<Output = Result<Identity, Error>> {
+        identity::solve(self, id)
+    }
+
+    /// domain returns the identity of the repo whose site is served at domain.
+    fn domain(&self, domain: &str) -> impl Future<Output = Result<Identity, Error>> {
+        domain::solve(self, domain)
+    }
+
+    /// record returns the value of the record at collection/rkey in did's repo.
+    fn record(
+        &self,
+        pds: String,
+        did: String,
+        collection: String,
+        rkey: String,
+    ) -> impl Future<Output = Result<serde_json::Value, Error>> {
+        async move {
+            atproto::fetch_record(self, pds, did, collection, rkey)
+                .await
+                .map(|r| r.value)
+        }
+    }
+
+    /// blob returns the blob with the given CID in did's repo.
+    fn blob(
+        &self,
+        pds: String,
+        did: String,
+        cid: String,
+    ) -> impl Future<Output = Result<Blob, Error>> {
+        async move {
+            let resp = atproto::get_blob(self, pds, did, cid).await?;
+
+            Ok(Blob {
+                mime_type: resp.header("Content-Type").map(|m| m.to_string()),
+                data: resp.body,
+            })
+        }
+    }
+
+    /// blob_stream returns the blob with the given CID in did's repo as it's read, ending with an
+    /// error if it doesn't hash to that CID.
+    fn blob_stream(
+        &self,
+        pds: String,
+        did: String,
+        cid: String,
+    ) -> impl Future<Output = Result<Streamed<Self::BlobStream>, Error>>;
+
+    /// cached_blob returns the blob with the given CID if the host keeps it, checked already.
+    fn cached_blob(&self, _cid: &str) -> impl Future<Output = Option<Blob>> {
+        async { None }
+    }
+
+    /// blob_range returns range of the blob with the given CID in did's repo as the PDS serves it,
+    /// or None if the PDS sent the whole blob instead, or didn't say which range it sent.
+    fn blob_range(
+        &self,
+        pds: String,
+        did: String,
+        cid: String,
+        range: Range,
+    ) -> impl Future<Output = Result<Option<Partial<Self::BlobStream>>, Error>>;
+}
+
+/// Worker is the service worker the renderer runs in.
+#[derive(Clone, Copy)]
+pub struct Worker;
+
+impl Host for Worker {
+    type BlobStream = LocalBoxStream<'static, Result<Vec<u8>, Error>>;
+
+    async fn fetch(&self, url: &str, headers: &[(&str, &str)]) -> Result<Response, Error> {
+        let resp = atproto::fetch(url.to_string(), RequestMode::Cors, headers).await?;
+
+        let headers = js_sys::try_iter(&resp.headers())?
+            .into_iter()
+            .flatten()
+            .filter_map(|h| {
+                let h = js_sys::Array::from(&h.ok()?);
+                Some((h.get(0).as_string()?, h.get(1).as_string()?))
+            })
+            .collect();
+
+        let body = JsFuture::from(resp.array_buffer()?).await?;
+
+        Ok(Response {
+            status: resp.status(),
+            headers,
+            body: Uint8Array::new(&body).to_vec(),
+        })
+    }
+
+    fn config(&self) -> &Config {
+        config::get()
+    }
+
+    fn now(&self) -> f64 {
+        js_sys::Date::now()
+    }
+
+    async fn identity(&self, id: &str) -> Result<Identity, Error> {
+        identity::resolve(id).await
+    }
+
+    async fn domain(&self, domain: &str) -> Result<Identity, Error> {
+        domain::resolve(domain).await
+    }
+
+    async fn record(
+        &self,
+        pds: String,
+        did: String,
+        collection: String,
+        rkey: String,
+    ) -> Result<serde_json::Value, Error> {
+        atproto::record(pds, did, collection, rkey).await
+    }
+
+    async fn blob(&self, pds: String, did: String, cid: String) -> Result<Blob, Error> {
+        let data = atproto::blob(pds, did, cid).await?;
+
+        Ok(Blob {
+            data: Uint8Array::new(&data.value).to_vec(),
+            mime_type: data.mime_type,
+        })
+    }
+
+    async fn blob_stream(
+        &self,
+        pds: String,
+        did: String,
+        cid: String,
+    ) -> Result<Streamed<Self::BlobStream>, Error> {
+        atproto::blob_stream(pds, did, cid).await
+    }
+
+    async fn cached_blob(&self, cid: &str) -> Option<Blob> {
+        let data = atproto::cached(cid).await?;
+
+        Some(Blob {
+            data: Uint8Array::new(&data.value).to_vec(),
+            mime_type: data.mime_type,
+        })
+    }
+
+    async fn blob_range(
+        &self,
+        pds: String,
+        did: String,
+        cid: String,
+        range: Range,
+    ) -> Result<Option<Partial<Self::BlobStream>>, Error> {
+        atproto::blob_range(pds, did, cid, range).await
+    }
+}
+
+/// Native is a host outside of the browser, with its own configuration.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct Native {
+    client: reqwest::Client,
+    config: Config,
+    /// Server standing in for every web server, if any.
+    web: Option<String>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Native {
+    pub fn new(config: Config) -> Self {
+        // reads time out rather than whole requests, so that big blobs can still be streamed
+        let client = reqwest::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .read_timeout(READ_TIMEOUT)
+            .build()
+            .expect("can't set up the HTTP client");
+
+        Native {
+            client,
+            config,
+            web: None,
+        }
+    }
+
+    /// with_web makes requests to web servers go to the server at web instead, at
+    /// /{domain}{path}, so that tests never leave it.
+    pub fn with_web(self, web: String) -> Self {
+        Native {
+            web: Some(web),
+            ..self
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Host for Native {
+    type BlobStream = BoxStream<'static, Result<Vec<u8>, Error>>;
+
+    async fn fetch(&self, url: &str, headers: &[(&str, &str)]) -> Result<Response, Error> {
+        log::debug!("getting {}", url);
+
+        let mut req = self.client.get(url);
+        for (k, v) in headers {
+            req = req.header(*k, *v);
+        }
+
+        let resp = req
+            .send()
+            .await
+            .map_err(|e| Error::FetchError(e.to_string()))?;
+
+        let headers = resp
+            .headers()
+            .iter()
+            .filter_map(|(k, v)| Some((k.to_string(), v.to_str().ok()?.to_string())))
+            .collect();
+
+        Ok(Response {
+            status: resp.status().as_u16(),
+            headers,
+            body: resp
+                .bytes()
+                .await
+                .map_err(|e| Error::FetchError(e.to_string()))?
+                .to_vec(),
+        })
+    }
+
+    fn config(&self) -> &Config {
+        &self.config
+    }
+
+    fn now(&self) -> f64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_millis() as f64)
+            .unwrap_or_default()
+    }
+
+    fn web_url(&self, domain: &str, path: &str) -> String {
+        match &self.web {
+            Some(web) => format!("{}/{}{}", web, domain, path),
+            None => format!("https://{}{}", domain, path),
+        }
+    }
+
+    async fn blob_stream(
+        &self,
+        pds: String,
+        did: String,
+        cid: String,
+    ) -> Result<Streamed<Self::BlobStream>, Error> {
+        let parsed = ipld::Cid::parse(&cid)?;
+        let url = atproto::blob_url(pds, did, &cid);
+
+        log::debug!("streaming {}", url);
+
+        let resp = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| Error::FetchError(e.to_string()))?;
+
+        let status = resp.status().as_u16();
+        if status != 200 {
+            let body = resp.bytes().await.unwrap_or_default();
+            let body = serde_json::from_slice(&body).unwrap_or_default();
+
+            return Err(atproto::blob_error(cid, status, &body));
+        }
+
+        Ok(Streamed {
+            mime_type: header(&resp, "Content-Type"),
+            size: resp.content_length(),
+            body: verify::blob_stream(parsed, chunks(resp)).boxed(),
+        })
+    }
+
+    async fn blob_range(
+        &self,
+        pds: String,
+        did: String,
+        cid: String,
+        range: Range,
+    ) -> Result<Option<Partial<Self::BlobStream>>, Error> {
+        let url = atproto::blob_url(pds, did, &cid);
+
+        log::debug!("getting {} of {}", range, url);
+
+        let resp = self
+            .client
+            .get(url)
+            .header("Range", range.to_string())
+            .send()
+            .await
+            .map_err(|e| Error::FetchError(e.to_string()))?;
+
+        let status = resp.status().as_u16();
+        let content_range = header(&resp, "Content-Range");
+
+        match status {
+            206 if content_range.is_none() => Ok(None),
+            206 | 416 => Ok(Some(Partial {
+                status,
+                content_range,
+                mime_type: header(&resp, "Content-Type"),
+                body: chunks(resp).boxed(),
+            })),
+            200 => Ok(None),
+            _ => {
+                let body = resp.bytes().await.unwrap_or_default();
+                let body = serde_json::from_slice(&body).unwrap_or_default();
+
+                Err(atproto::blob_error(cid, status, &body))
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn header(resp: &reqwest::Response, name: &str) -> Option<String> {
+    resp.headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_string())
+}
+
+/// chunks returns the body of resp as it's read.
+#[cfg(not(target_arch = "wasm32"))]
+fn chunks(resp: reqwest::Response) -> impl Stream<Item = Result<Vec<u8>, Error>> {
+    // the response is dropped once it errors, so that it's never read past the error
+    futures::stream::unfold(Some(resp), |resp| async move {
+        let mut resp = resp?;
+
+        match resp.chunk().await {
+            Ok(Some(chunk)) => Some((Ok(chunk.to_vec()), Some(resp))),
+            Ok(None) => None,
+            Err(e) => Some((Err(Error::FetchError(e.to_string())), None)),
+        }
+    })
+}
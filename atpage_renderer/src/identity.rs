@@ -0,0 +1,122 @@
+use crate::{
+    atproto::Error,
+    cache, did, handle,
+    host::{Host, Worker},
+};
+use serde::{Deserialize, Serialize};
+use std::{cell::RefCell, collections::HashMap};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::Response;
+
+const CACHE_NAME: &str = "atpage-identities";
+const CACHE_KIND: &str = "identity";
+
+thread_local! {
+    static IDENTITIES: RefCell<HashMap<String, Identity>> = RefCell::new(HashMap::new());
+}
+
+/// Identity is a handle or DID resolved to the DID and PDS serving its repo.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Identity {
+    pub did: String,
+    pub pds: String,
+
+    /// Handle the identity was resolved from, once checked to point back to did.
+    #[serde(default)]
+    pub handle: Option<String>,
+
+    /// Time after which the identity must be resolved again, in milliseconds since the epoch.
+    expires: f64,
+}
+
+impl Identity {
+    /// expired returns true if the identity must be resolved again, according to host's clock.
+    pub fn expired(&self, host: &impl Host) -> bool {
+        host.now() >= self.expires
+    }
+}
+
+/// resolve returns the identity of the repo id, a handle or a DID, points to, from memory,
+/// from the Cache API or by resolving it from scratch, in this order.
+/// Expired identities are still used when resolving them again fails, so that sites keep working
+/// offline.
+pub async fn resolve(id: &str) -> Result<Identity, Error> {
+    // handles are case-insensitive
+    let key = id.to_lowercase();
+
+    let cached = IDENTITIES.with_borrow(|ids| ids.get(&key).cloned());
+    let stale = match cached {
+        Some(id) if !id.expired(&Worker) => return Ok(id),
+        stale => stale,
+    };
+
+    let stale = match load(&key).await {
+        Some(id) if !id.expired(&Worker) => {
+            log::debug!("identity for {} loaded from cache", key);
+            IDENTITIES.with_borrow_mut(|ids| ids.insert(key, id.clone()));
+            return Ok(id);
+        }
+        loaded => stale.or(loaded),
+    };
+
+    let id = match (solve(&Worker, id).await, stale) {
+        (Ok(id), _) => id,
+        (Err(e), Some(stale)) => {
+            log::warn!("can't resolve {} again, using expired identity: {}", key, e);
+            return Ok(stale);
+        }
+        (Err(e), None) => return Err(e),
+    };
+
+    if let Err(e) = store(&key, &id).await {
+        log::debug!("can't persist identity for {}: {:?}", key, e);
+    }
+
+    IDENTITIES.with_borrow_mut(|ids| ids.insert(key, id.clone()));
+
+    Ok(id)
+}
+
+/// solve resolves id, a handle or a DID, from scratch, making sure that handle and DID point to
+/// each other.
+pub async fn solve(host: &impl Host, id: &str) -> Result<Identity, Error> {
+    let needs_resolution = !id.starts_with("did:");
+
+    log::debug!("solving did...");
+    let did = match needs_resolution {
+        true => handle::resolve(host, id.to_string()).await?,
+        false => id.to_string(),
+    };
+
+    log::debug!("solving pds...");
+    let doc = did::resolve(host, did.clone()).await?;
+
+    if needs_resolution {
+        doc.check_handle(id)?;
+    }
+
+    Ok(Identity {
+        did,
+        pds: doc.pds()?,
+        handle: needs_resolution.then(|| id.to_lowercase()),
+        expires: host.now() + host.config().identity_ttl as f64 * 1000.0,
+    })
+}
+
+async fn load(key: &str) -> Option<Identity> {
+    let c = cache::open(CACHE_NAME).await.ok()?;
+    let resp = cache::get(&c, &cache::key(CACHE_KIND, key)).await.ok()??;
+
+    let text = JsFuture::from(resp.text().ok()?).await.ok()?.as_string()?;
+
+    serde_json::from_str(&text).ok()
+}
+
+async fn store(key: &str, id: &Identity) -> Result<(), Error> {
+    let c = cache::open(CACHE_NAME).await?;
+
+    let body = serde_json::to_string(id).map_err(|e| Error::MalformedRecord(e.to_string()))?;
+    let resp = Response::new_with_opt_str(Some(&body))?;
+
+    Ok(cache::put(&c, &cache::key(CACHE_KIND, key), &resp).await?)
+}
@@ -0,0 +1,570 @@
+// Just enough IPLD to verify what a PDS serves: CIDs, DAG-CBOR and CAR files.
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+
+const CID_V1: u64 = 0x01;
+const SHA256_CODE: u64 = 0x12;
+const CID_TAG: u64 = 42;
+/// How deep lists and maps can nest, records and MST nodes need a handful of levels.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug)]
+pub enum Error {
+    UnexpectedEnd,
+    MalformedCID(String),
+    MalformedCBOR(String),
+    MalformedCAR(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::UnexpectedEnd => write!(f, "unexpected end of data"),
+            Error::MalformedCID(e) => write!(f, "malformed CID: {}", e),
+            Error::MalformedCBOR(e) => write!(f, "malformed DAG-CBOR: {}", e),
+            Error::MalformedCAR(e) => write!(f, "malformed CAR file: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Cid is a CIDv1, kept in its binary form.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Cid {
+    bytes: Vec<u8>,
+    digest_start: usize,
+    hash_code: u64,
+}
+
+impl Cid {
+    /// read parses the CID at the start of data, returning it along with the amount of bytes it spans.
+    pub fn read(data: &[u8]) -> Result<(Cid, usize), Error> {
+        let mut pos = 0;
+
+        let version = varint(data, &mut pos)?;
+        if version != CID_V1 {
+            return Err(Error::MalformedCID(format!(
+                "unsupported version {}",
+                version
+            )));
+        }
+
+        let _codec = varint(data, &mut pos)?;
+        let hash_code = varint(data, &mut pos)?;
+        let digest_len = length(varint(data, &mut pos)?)?;
+
+        let digest_start = pos;
+        take(data, &mut pos, digest_len)?;
+
+        Ok((
+            Cid {
+                bytes: data[..pos].to_vec(),
+                digest_start,
+                hash_code,
+            },
+            pos,
+        ))
+    }
+
+    /// parse decodes a multibase-encoded CID, as found in AT URIs and JSON records.
+    pub fn parse(s: &str) -> Result<Cid, Error> {
+        let (_, bytes) = multibase::decode(s).map_err(|e| Error::MalformedCID(e.to_string()))?;
+
+        let (cid, len) = Cid::read(&bytes)?;
+        if len != bytes.len() {
+            return Err(Error::MalformedCID(format!("trailing data after {}", s)));
+        }
+
+        Ok(cid)
+    }
+
+    /// matches returns true if data hashes to this CID.
+    pub fn matches(&self, data: &[u8]) -> bool {
+        self.matches_digest(&Sha256::digest(data))
+    }
+
+    /// matches_digest returns true if digest, the SHA-256 hash of some data, is the one of this CID.
+    pub fn matches_digest(&self, digest: &[u8]) -> bool {
+        self.hash_code == SHA256_CODE && self.bytes.get(self.digest_start..) == Some(digest)
+    }
+}
+
+impl std::fmt::Display for Cid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            multibase::encode(multibase::Base::Base32Lower, &self.bytes)
+        )
+    }
+}
+
+/// Value is a decoded DAG-CBOR item.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Integer(i128),
+    Float(f64),
+    Bytes(Vec<u8>),
+    String(String),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+    Link(Cid),
+}
+
+impl Value {
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(m) => m.get(key),
+            _ => None,
+        }
+    }
+
+    pub fn as_link(&self) -> Option<&Cid> {
+        match self {
+            Value::Link(c) => Some(c),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Bytes(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i128> {
+        match self {
+            Value::Integer(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Value]> {
+        match self {
+            Value::List(l) => Some(l),
+            _ => None,
+        }
+    }
+
+    /// to_json converts the value to the JSON representation used by XRPC, where links
+    /// are {"$link": cid} and bytes are {"$bytes": base64}.
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Value::Null => serde_json::Value::Null,
+            Value::Bool(b) => serde_json::Value::Bool(*b),
+            Value::Integer(i) => match i64::try_from(*i) {
+                Ok(i) => serde_json::Value::from(i),
+                Err(_) => serde_json::Value::from(*i as f64),
+            },
+            Value::Float(f) => serde_json::Value::from(*f),
+            Value::Bytes(b) => {
+                let encoded = multibase::encode(multibase::Base::Base64, b);
+                // strip the multibase prefix
+                serde_json::json!({ "$bytes": encoded[1..] })
+            }
+            Value::String(s) => serde_json::Value::String(s.clone()),
+            Value::List(l) => serde_json::Value::Array(l.iter().map(|e| e.to_json()).collect()),
+            Value::Map(m) => {
+                serde_json::Value::Object(m.iter().map(|(k, v)| (k.clone(), v.to_json())).collect())
+            }
+            Value::Link(c) => serde_json::json!({ "$link": c.to_string() }),
+        }
+    }
+}
+
+/// decode decodes a single DAG-CBOR item spanning the whole of data.
+pub fn decode(data: &[u8]) -> Result<Value, Error> {
+    let mut pos = 0;
+    let value = decode_item(data, &mut pos, 0)?;
+
+    if pos != data.len() {
+        return Err(Error::MalformedCBOR("trailing data".to_string()));
+    }
+
+    Ok(value)
+}
+
+/// encode encodes value as canonical DAG-CBOR.
+pub fn encode(value: &Value) -> Vec<u8> {
+    let mut out = vec![];
+    encode_item(value, &mut out);
+    out
+}
+
+/// Car is a CAR file whose blocks have all been checked against their CIDs.
+pub struct Car {
+    pub roots: Vec<Cid>,
+    blocks: HashMap<Cid, Vec<u8>>,
+}
+
+impl Car {
+    pub fn read(data: &[u8]) -> Result<Car, Error> {
+        let mut pos = 0;
+
+        let header_len = length(varint(data, &mut pos)?)?;
+        let header = decode(take(data, &mut pos, header_len)?)?;
+
+        let roots = header
+            .get("roots")
+            .and_then(|r| r.as_list())
+            .ok_or(Error::MalformedCAR("no roots in header".to_string()))?
+            .iter()
+            .filter_map(|r| r.as_link().cloned())
+            .collect::<Vec<Cid>>();
+
+        let mut blocks = HashMap::new();
+        while pos < data.len() {
+            let block_len = length(varint(data, &mut pos)?)?;
+            let block = take(data, &mut pos, block_len)?;
+
+            let (cid, cid_len) = Cid::read(block)?;
+            let content = &block[cid_len..];
+
+            if !cid.matches(content) {
+                return Err(Error::MalformedCAR(format!(
+                    "block content doesn't match CID {}",
+                    cid
+                )));
+            }
+
+            blocks.insert(cid, content.to_vec());
+        }
+
+        Ok(Car { roots, blocks })
+    }
+
+    pub fn block(&self, cid: &Cid) -> Option<&[u8]> {
+        self.blocks.get(cid).map(|b| b.as_slice())
+    }
+}
+
+fn varint(data: &[u8], pos: &mut usize) -> Result<u64, Error> {
+    let mut ret = 0u64;
+    for shift in (0..64).step_by(7) {
+        let b = *data.get(*pos).ok_or(Error::UnexpectedEnd)?;
+        *pos += 1;
+
+        ret |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Ok(ret);
+        }
+    }
+
+    Err(Error::MalformedCID("varint too long".to_string()))
+}
+
+/// length returns n as a length of data, which can't be more than fits in memory.
+fn length(n: u64) -> Result<usize, Error> {
+    usize::try_from(n).map_err(|_| Error::UnexpectedEnd)
+}
+
+fn take<'a>(data: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8], Error> {
+    let end = pos.checked_add(len).ok_or(Error::UnexpectedEnd)?;
+    let ret = data.get(*pos..end).ok_or(Error::UnexpectedEnd)?;
+    *pos = end;
+    Ok(ret)
+}
+
+fn argument(data: &[u8], pos: &mut usize, info: u8) -> Result<u64, Error> {
+    Ok(match info {
+        0..=23 => info as u64,
+        24 => take(data, pos, 1)?[0] as u64,
+        25 => u16::from_be_bytes(take(data, pos, 2)?.try_into().unwrap()) as u64,
+        26 => u32::from_be_bytes(take(data, pos, 4)?.try_into().unwrap()) as u64,
+        27 => u64::from_be_bytes(take(data, pos, 8)?.try_into().unwrap()),
+        _ => {
+            return Err(Error::MalformedCBOR(format!(
+                "unsupported additional info {}",
+                info
+            )))
+        }
+    })
+}
+
+/// decode_item decodes the item at pos, nested in depth lists and maps.
+fn decode_item(data: &[u8], pos: &mut usize, depth: usize) -> Result<Value, Error> {
+    if depth > MAX_DEPTH {
+        return Err(Error::MalformedCBOR("items nested too deep".to_string()));
+    }
+
+    let initial = take(data, pos, 1)?[0];
+    let (major, info) = (initial >> 5, initial & 0x1f);
+
+    if major == 7 {
+        return match info {
+            20 => Ok(Value::Bool(false)),
+            21 => Ok(Value::Bool(true)),
+            22 => Ok(Value::Null),
+            27 => Ok(Value::Float(f64::from_bits(argument(data, pos, info)?))),
+            _ => Err(Error::MalformedCBOR(format!(
+                "unsupported simple value {}",
+                info
+            ))),
+        };
+    }
+
+    let arg = argument(data, pos, info)?;
+
+    match major {
+        0 => Ok(Value::Integer(arg as i128)),
+        1 => Ok(Value::Integer(-1 - arg as i128)),
+        2 => Ok(Value::Bytes(take(data, pos, length(arg)?)?.to_vec())),
+        3 => String::from_utf8(take(data, pos, length(arg)?)?.to_vec())
+            .map(Value::String)
+            .map_err(|e| Error::MalformedCBOR(e.to_string())),
+        4 => {
+            let mut list = vec![];
+            for _ in 0..arg {
+                list.push(decode_item(data, pos, depth + 1)?);
+            }
+            Ok(Value::List(list))
+        }
+        5 => {
+            let mut map = BTreeMap::new();
+            for _ in 0..arg {
+                let key = match decode_item(data, pos, depth + 1)? {
+                    Value::String(s) => s,
+                    _ => return Err(Error::MalformedCBOR("map key is not a string".to_string())),
+                };
+                map.insert(key, decode_item(data, pos, depth + 1)?);
+            }
+            Ok(Value::Map(map))
+        }
+        6 if arg == CID_TAG => match decode_item(data, pos, depth + 1)? {
+            // CIDs are tagged byte strings prefixed by the identity multibase
+            Value::Bytes(b) if b.first() == Some(&0) => {
+                let (cid, len) = Cid::read(&b[1..])?;
+                match len == b.len() - 1 {
+                    true => Ok(Value::Link(cid)),
+                    false => Err(Error::MalformedCID("trailing data in link".to_string())),
+                }
+            }
+            _ => Err(Error::MalformedCBOR("malformed link".to_string())),
+        },
+        _ => Err(Error::MalformedCBOR(format!(
+            "unsupported major type {} with argument {}",
+            major, arg
+        ))),
+    }
+}
+
+fn encode_head(major: u8, arg: u64, out: &mut Vec<u8>) {
+    let major = major << 5;
+    match arg {
+        0..=23 => out.push(major | arg as u8),
+        24..=0xff => out.extend([major | 24, arg as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend((arg as u16).to_be_bytes());
+        }
+        0x10000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend((arg as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend(arg.to_be_bytes());
+        }
+    }
+}
+
+fn encode_item(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Null => out.push(0xf6),
+        Value::Bool(false) => out.push(0xf4),
+        Value::Bool(true) => out.push(0xf5),
+        Value::Integer(i) if *i >= 0 => encode_head(0, *i as u64, out),
+        Value::Integer(i) => encode_head(1, (-1 - *i) as u64, out),
+        Value::Float(f) => {
+            out.push(0xfb);
+            out.extend(f.to_bits().to_be_bytes());
+        }
+        Value::Bytes(b) => {
+            encode_head(2, b.len() as u64, out);
+            out.extend(b);
+        }
+        Value::String(s) => {
+            encode_head(3, s.len() as u64, out);
+            out.extend(s.as_bytes());
+        }
+        Value::List(l) => {
+            encode_head(4, l.len() as u64, out);
+            l.iter().for_each(|e| encode_item(e, out));
+        }
+        Value::Map(m) => {
+            // canonical DAG-CBOR sorts keys by length first, then bytewise
+            let mut keys = m.keys().collect::<Vec<&String>>();
+            keys.sort_by(|a, b| a.len().cmp(&b.len()).then(a.cmp(b)));
+
+            encode_head(5, m.len() as u64, out);
+            for k in keys {
+                encode_head(3, k.len() as u64, out);
+                out.extend(k.as_bytes());
+                encode_item(&m[k], out);
+            }
+        }
+        Value::Link(c) => {
+            encode_head(6, CID_TAG, out);
+            encode_head(2, c.bytes.len() as u64 + 1, out);
+            out.push(0);
+            out.extend(&c.bytes);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    const DAG_CBOR: u8 = 0x71;
+
+    /// cid returns the CID of a DAG-CBOR block holding data.
+    pub(crate) fn cid(data: &[u8]) -> Cid {
+        let mut bytes = vec![CID_V1 as u8, DAG_CBOR, SHA256_CODE as u8, 0x20];
+        bytes.extend_from_slice(&Sha256::digest(data));
+
+        Cid::read(&bytes).unwrap().0
+    }
+
+    fn write_varint(mut n: u64, out: &mut Vec<u8>) {
+        while n >= 0x80 {
+            out.push(n as u8 | 0x80);
+            n >>= 7;
+        }
+        out.push(n as u8);
+    }
+
+    /// car returns a CAR file holding blocks, each stored under its own CID, with root as its
+    /// root.
+    pub(crate) fn car(root: &Cid, blocks: &[&[u8]]) -> Vec<u8> {
+        let header = encode(&Value::Map(BTreeMap::from([
+            ("version".to_string(), Value::Integer(1)),
+            (
+                "roots".to_string(),
+                Value::List(vec![Value::Link(root.clone())]),
+            ),
+        ])));
+
+        let mut out = vec![];
+        write_varint(header.len() as u64, &mut out);
+        out.extend(header);
+
+        for block in blocks {
+            let cid = cid(block);
+            write_varint((cid.bytes.len() + block.len()) as u64, &mut out);
+            out.extend(&cid.bytes);
+            out.extend(*block);
+        }
+
+        out
+    }
+
+    fn record() -> Value {
+        Value::Map(BTreeMap::from([
+            ("title".to_string(), Value::String("Hello".to_string())),
+            ("size".to_string(), Value::Integer(-300)),
+            ("draft".to_string(), Value::Bool(false)),
+            ("ref".to_string(), Value::Link(cid(b"blob"))),
+            ("data".to_string(), Value::Bytes(vec![0, 1, 2])),
+            ("tags".to_string(), Value::List(vec![Value::Null])),
+        ]))
+    }
+
+    #[test]
+    fn round_trips_values() {
+        assert_eq!(decode(&encode(&record())).unwrap(), record());
+    }
+
+    #[test]
+    fn sorts_keys_by_length_first() {
+        let map = Value::Map(BTreeMap::from([
+            ("aa".to_string(), Value::Null),
+            ("b".to_string(), Value::Null),
+        ]));
+
+        assert_eq!(encode(&map), b"\xa2\x61b\xf6\x62aa\xf6");
+    }
+
+    #[test]
+    fn refuses_trailing_data() {
+        let mut data = encode(&record());
+        data.push(0xf6);
+
+        assert!(matches!(decode(&data), Err(Error::MalformedCBOR(_))));
+    }
+
+    #[test]
+    fn parses_cids() {
+        let cid = cid(b"block");
+
+        assert_eq!(Cid::parse(&cid.to_string()).unwrap(), cid);
+        assert!(cid.matches(b"block"));
+        assert!(!cid.matches(b"other"));
+    }
+
+    #[test]
+    fn reads_cars() {
+        let block = encode(&record());
+        let car = Car::read(&car(&cid(&block), &[&block])).unwrap();
+
+        assert_eq!(car.roots, vec![cid(&block)]);
+        assert_eq!(car.block(&cid(&block)), Some(block.as_slice()));
+    }
+
+    #[test]
+    fn refuses_tampered_blocks() {
+        let block = encode(&record());
+        let mut data = car(&cid(&block), &[&block]);
+
+        // the title is the last thing in the block
+        let last = data.len() - 1;
+        data[last] ^= 1;
+
+        assert!(matches!(Car::read(&data), Err(Error::MalformedCAR(_))));
+    }
+
+    #[test]
+    fn refuses_truncated_cars() {
+        let block = encode(&record());
+        let data = car(&cid(&block), &[&block]);
+
+        assert!(matches!(
+            Car::read(&data[..data.len() - 1]),
+            Err(Error::UnexpectedEnd)
+        ));
+    }
+
+    #[test]
+    fn refuses_deeply_nested_items() {
+        // lists of one item, each holding the next one
+        let mut data = vec![0x81; 100_000];
+        data.push(0xf6);
+
+        assert!(matches!(decode(&data), Err(Error::MalformedCBOR(_))));
+    }
+
+    #[test]
+    fn refuses_cids_longer_than_their_data() {
+        // a digest length of u64::MAX
+        let mut data = vec![0x01, 0x55, 0x12];
+        data.extend([0xff; 9]);
+        data.extend([0x01, 0xaa, 0xbb]);
+
+        assert!(matches!(Cid::read(&data), Err(Error::UnexpectedEnd)));
+
+        // a CID claiming a short digest reads, but matches nothing
+        let (cid, _) = Cid::read(&[0x01, 0x55, 0x12, 0x02, 0xaa, 0xbb]).unwrap();
+        assert!(!cid.matches(b"block"));
+    }
+}
@@ -1,14 +1,28 @@
-mod atproto;
-use std::collections::HashMap;
-
-use atproto::parse_at_url;
-use js_sys::Uint8Array;
-use shared::atproto::ATURL;
+pub mod atproto;
+mod cache;
+pub mod conditional;
+pub mod config;
+pub mod did;
+pub mod domain;
+pub mod error;
+pub mod handle;
+pub mod host;
+pub mod identity;
+pub mod ipld;
+pub mod range;
+pub mod records;
+pub mod sanitize;
+pub mod security;
+pub mod serve;
+pub mod sniff;
+pub mod verify;
+
+use conditional::Conditions;
+use futures::StreamExt;
+use host::{Host, Worker};
+use serve::Body;
 use wasm_bindgen::prelude::*;
-use web_sys::{Headers, Response, ResponseInit};
-
-static CACHED_DID_DATA: tokio::sync::OnceCell<(String, String)> =
-    tokio::sync::OnceCell::const_new();
+use web_sys::{Headers, Response, ResponseInit, WorkerGlobalScope};
 
 #[wasm_bindgen(start)]
 pub fn init_wasm_log() {
@@ -25,7 +39,8 @@ pub fn init_wasm_log() {
 #[wasm_bindgen]
 pub async fn is_at(event: web_sys::FetchEvent) -> bool {
     let u = event.request().url();
-    parse_at_url(u.clone()).is_some()
+    atproto::at_path(&u).is_some()
+        || (event.request().method() == "GET" && atproto::site_path(&u).is_some())
 }
 
 #[derive(Debug)]
@@ -43,92 +58,65 @@ impl std::fmt::Display for Error {
 
 impl std::error::Error for Error {}
 
-impl Into<JsValue> for Error {
-    fn into(self) -> JsValue {
-        JsValue::from(self.to_string())
+impl From<Error> for JsValue {
+    fn from(e: Error) -> Self {
+        JsValue::from(e.to_string())
     }
 }
 
 #[wasm_bindgen]
-pub async fn resolve(event: web_sys::FetchEvent) -> Result<web_sys::Response, Error> {
+pub async fn resolve(event: web_sys::FetchEvent) -> Result<web_sys::Response, JsValue> {
     let u = event.request().url();
 
     log::debug!("fetching: {}", u);
 
-    let atu = match parse_at_url(u.clone()) {
-        Some(s) => s,
-        None => return Err(Error::NotATURI(u)),
-    }
-    .unwrap();
+    // /at/ URLs always work, plain paths only when a site is configured or looked up by domain
+    let path = match atproto::at_path(&u).or_else(|| atproto::site_path(&u)) {
+        Some(path) => path,
+        None => return Err(Error::NotATURI(u).into()),
+    };
 
-    let (did, pds) = did_pds(&atu).await;
+    let worker = js_sys::global().dyn_into::<WorkerGlobalScope>()?;
 
-    match atu.blob {
-        true => blob(pds, did, atu).await,
-        false => page(pds, did, atu).await,
-    }
-}
+    let req = serve::Request {
+        origin: worker.location().origin(),
+        path,
+        site: atproto::site(),
+        conditions: Conditions::from_request(&event.request()),
+    };
 
-async fn blob(pds: String, did: String, atu: ATURL) -> Result<web_sys::Response, Error> {
-    log::debug!("processing blob!");
-    let data = atproto::data(pds.clone(), did.clone(), atu.key, atu.blob)
-        .await
-        .expect_throw("object not found");
+    response(serve::route(&Worker, req).await)
+}
 
+/// response turns resp into the browser's kind of response, reading blobs and chunked files as
+/// the browser reads them.
+fn response(
+    resp: serve::Response<<Worker as Host>::BlobStream>,
+) -> Result<web_sys::Response, JsValue> {
     let ri = ResponseInit::new();
-    ri.set_status(200);
+    ri.set_status(resp.status);
 
-    let h = Headers::new().unwrap();
-    h.set("Content-Type", &data.mime_type.unwrap_or_default())
-        .unwrap();
+    let h = Headers::new()?;
+    for (name, value) in resp.headers {
+        h.set(&name, &value)?;
+    }
     ri.set_headers(&h);
 
-    log::debug!("got blob, creating Uint8Array");
-
-    let buffer = Uint8Array::new(&data.value);
-
-    log::debug!("blob: {:?}", buffer);
-
-    Ok(Response::new_with_opt_js_u8_array_and_init(Some(&buffer), &ri).unwrap())
-}
-
-async fn page(pds: String, did: String, atu: ATURL) -> Result<web_sys::Response, Error> {
-    log::debug!("processing page!");
-    let data = atproto::data(pds.clone(), did.clone(), atu.collection, atu.blob)
-        .await
-        .expect_throw("object not found");
-    let webpage = atproto::webpage(data.value.clone(), atu.key, pds.clone(), did.clone())
-        .await
-        .expect_throw("can't find webpages");
-
-    let mut headers = HashMap::new();
-    headers.insert("Content-Type", "text/html; charset=utf-8");
-    let ri = ResponseInit::new();
-    ri.set_status(200);
-    ri.set_headers(&serde_wasm_bindgen::to_value(&headers).unwrap());
+    match resp.body {
+        Body::Empty => Response::new_with_opt_str_and_init(None, &ri),
+        Body::Bytes(mut data) => Response::new_with_opt_u8_array_and_init(Some(&mut data), &ri),
+        Body::Stream(content) => {
+            Response::new_with_opt_readable_stream_and_init(Some(&atproto::readable(content)), &ri)
+        }
+        Body::Chunks { pds, did, parts } => {
+            let chunks = serve::chunks(&Worker, pds, did, parts).map(|chunk| match chunk {
+                Ok(data) => Ok(JsValue::from(js_sys::Uint8Array::from(&data[..]))),
+                Err(e) => Err(JsValue::from(format!("can't fetch chunk: {}", e))),
+            });
 
-    Ok(Response::new_with_opt_str_and_init(Some(&webpage.content), &ri).unwrap())
-}
+            let body = wasm_streams::ReadableStream::from_stream(chunks).into_raw();
 
-async fn did_pds(atu: &ATURL) -> (String, String) {
-    CACHED_DID_DATA
-        .get_or_init(async || {
-            log::debug!("solving did...");
-            let did = atu.did.clone();
-            let did = match atu.needs_resolution {
-                true => atproto::solve_did(did)
-                    .await
-                    .expect_throw("can't solve did"),
-                false => did,
-            };
-
-            log::debug!("solving pds...");
-            let pds = atproto::solve_pds(did.clone())
-                .await
-                .expect_throw("can't find pds for did");
-
-            (did, pds)
-        })
-        .await
-        .clone()
+            Response::new_with_opt_readable_stream_and_init(Some(&body), &ri)
+        }
+    }
 }
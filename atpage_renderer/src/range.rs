@@ -0,0 +1,64 @@
+/// Range is a single byte range, as sent in a Range header.
+#[derive(Debug, Clone, Copy)]
+pub enum Range {
+    /// Bytes from the first offset up to the second one, or to the end, inclusive.
+    From(u64, Option<u64>),
+
+    /// The given amount of bytes at the end.
+    Suffix(u64),
+}
+
+/// parse parses a Range header, returning None for malformed or multi-range ones, which
+/// are answered with the whole content.
+pub fn parse(header: &str) -> Option<Range> {
+    let spec = header.trim().strip_prefix("bytes=")?;
+
+    if spec.contains(',') {
+        return None;
+    }
+
+    let (start, end) = spec.split_once('-')?;
+    let (start, end) = (start.trim(), end.trim());
+
+    match (start.is_empty(), end.is_empty()) {
+        (true, false) => Some(Range::Suffix(end.parse().ok()?)),
+        (false, true) => Some(Range::From(start.parse().ok()?, None)),
+        (false, false) => {
+            let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+            match start <= end {
+                true => Some(Range::From(start, Some(end))),
+                false => None,
+            }
+        }
+        (true, true) => None,
+    }
+}
+
+impl Range {
+    /// resolve returns the first and last byte the range spans in content of size bytes,
+    /// or None if it can't be satisfied.
+    pub fn resolve(&self, size: u64) -> Option<(u64, u64)> {
+        match *self {
+            _ if size == 0 => None,
+            Range::From(start, _) if start >= size => None,
+            Range::From(start, end) => Some((start, end.unwrap_or(size - 1).min(size - 1))),
+            Range::Suffix(0) => None,
+            Range::Suffix(len) => Some((size.saturating_sub(len), size - 1)),
+        }
+    }
+}
+
+impl std::fmt::Display for Range {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Range::From(start, Some(end)) => write!(f, "bytes={}-{}", start, end),
+            Range::From(start, None) => write!(f, "bytes={}-", start),
+            Range::Suffix(len) => write!(f, "bytes=-{}", len),
+        }
+    }
+}
+
+/// content_range formats the Content-Range header for bytes start to end of size.
+pub fn content_range(start: u64, end: u64, size: u64) -> String {
+    format!("bytes {}-{}/{}", start, end, size)
+}
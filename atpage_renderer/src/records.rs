@@ -0,0 +1,447 @@
+use crate::atproto::Error;
+use serde_json::Value;
+
+/// Asset is a standalone asset, stored either in a blob or in a chunked record.
+#[derive(Debug, PartialEq)]
+pub enum Asset {
+    Blob(String),
+    Chunked(String),
+}
+
+/// Chunked is a file split in several blobs, to be concatenated in order.
+#[derive(Debug)]
+pub struct Chunked {
+    pub mime_type: String,
+    pub size: u64,
+    pub chunks: Vec<Chunk>,
+}
+
+/// Chunk is one of the blobs a chunked file is split in.
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub cid: String,
+    pub size: u64,
+}
+
+/// Part is the slice of a chunk that a byte range of a chunked file spans, from start included
+/// to end excluded.
+#[derive(Debug, PartialEq)]
+pub struct Part {
+    pub cid: String,
+    pub start: u64,
+    pub end: u64,
+}
+
+impl Chunked {
+    /// parts returns the slices of chunks holding bytes start to end of the file, inclusive.
+    pub fn parts(&self, start: u64, end: u64) -> Vec<Part> {
+        let mut parts = vec![];
+        let mut offset = 0;
+
+        for chunk in self.chunks.iter() {
+            let (from, to) = (offset, offset + chunk.size);
+            offset = to;
+
+            if to <= start || from > end {
+                continue;
+            }
+
+            parts.push(Part {
+                cid: chunk.cid.clone(),
+                start: start.saturating_sub(from),
+                end: (end + 1).min(to) - from,
+            });
+        }
+
+        parts
+    }
+}
+
+/// SitePath is what a plain site path points to.
+#[derive(Debug, PartialEq)]
+pub enum SitePath {
+    /// A page, with its record key.
+    Page(String),
+    Asset(Asset),
+}
+
+/// PageContent points to the content of a page.
+#[derive(Debug)]
+pub struct PageContent {
+    /// CID of the blob holding the page.
+    pub cid: String,
+
+    /// MIME type the content blob was uploaded with.
+    pub mime_type: Option<String>,
+
+    /// When the page was last published, if known, as an RFC 3339 timestamp.
+    pub updated_at: Option<String>,
+}
+
+impl PageContent {
+    /// content_type returns the Content-Type the page is served with.
+    /// Pages are HTML, so content uploaded with any other type is served as HTML anyway rather
+    /// than shown as source or downloaded.
+    pub fn content_type(&self) -> String {
+        match self.mime_type.as_deref().map(|m| m.trim()) {
+            Some(m) if m.starts_with("application/xhtml+xml") => m.to_string(),
+            Some(m) if m.starts_with("text/html") && m.contains("charset") => m.to_string(),
+            _ => "text/html; charset=utf-8".to_string(),
+        }
+    }
+}
+
+/// link returns the CID a blob reference points to.
+fn link(blob: &Value) -> Option<&str> {
+    blob.get("ref")
+        .and_then(|e| e.get("$link"))
+        .and_then(|e| e.as_str())
+}
+
+/// asset returns where the standalone asset published at path in site is stored.
+pub fn asset(site: &Value, path: &str) -> Option<Result<Asset, Error>> {
+    let asset = site
+        .get("assets")
+        .and_then(|e| e.as_array())
+        .and_then(|e| {
+            e.iter()
+                .find(|a| a.get("path").and_then(|p| p.as_str()) == Some(path))
+        })?;
+
+    if let Some(rkey) = asset.get("chunked").and_then(|e| e.as_str()) {
+        return Some(Ok(Asset::Chunked(rkey.to_string())));
+    }
+
+    Some(match asset.get("blob").and_then(link) {
+        Some(cid) => Ok(Asset::Blob(cid.to_string())),
+        None => Err(Error::MalformedRecord(path.to_string())),
+    })
+}
+
+/// path returns what's published at path in site, looking it up in the path index first and
+/// among standalone assets then.
+/// Directories map to their index.html, and paths without extension to the matching .html page.
+pub fn path(site: &Value, path: &str) -> Result<SitePath, Error> {
+    let candidates = match path.ends_with("/") {
+        true => vec![format!("{}index.html", path)],
+        false => vec![
+            path.to_string(),
+            format!("{}.html", path),
+            format!("{}/index.html", path),
+        ],
+    };
+
+    let pages = site.get("pages").and_then(|e| e.as_array());
+
+    for c in candidates.iter() {
+        let rkey = pages.and_then(|e| {
+            e.iter()
+                .find(|p| p.get("path").and_then(|p| p.as_str()) == Some(c))
+                .and_then(|p| p.get("rkey"))
+                .and_then(|r| r.as_str())
+        });
+
+        if let Some(rkey) = rkey {
+            return Ok(SitePath::Page(rkey.to_string()));
+        }
+    }
+
+    match asset(site, path) {
+        Some(asset) => Ok(SitePath::Asset(asset?)),
+        None => Err(Error::NoRecordFound(path.to_string())),
+    }
+}
+
+/// stored_mime_type returns the MIME type the blob cid was uploaded with, as recorded in site.
+pub fn stored_mime_type(site: &Value, cid: &str) -> Option<String> {
+    let manifest = site.get("blobs").and_then(|e| e.as_array());
+    let assets = site
+        .get("assets")
+        .and_then(|e| e.as_array())
+        .map(|a| a.iter().filter_map(|e| e.get("blob")).collect::<Vec<_>>());
+
+    manifest
+        .into_iter()
+        .flatten()
+        .chain(assets.into_iter().flatten())
+        .find(|b| link(b) == Some(cid))
+        .and_then(|b| b.get("mimeType"))
+        .and_then(|e| e.as_str())
+        .map(|e| e.to_string())
+}
+
+/// MIME types scripts are published with.
+const SCRIPT_TYPES: [&str; 4] = [
+    "text/javascript",
+    "application/javascript",
+    "application/x-javascript",
+    "text/ecmascript",
+];
+
+/// scripts returns the paths of the standalone assets in site that are stored in a blob typed
+/// as a script.
+pub fn scripts(site: &Value) -> Vec<String> {
+    site.get("assets")
+        .and_then(|e| e.as_array())
+        .into_iter()
+        .flatten()
+        .filter(|a| {
+            a.get("blob")
+                .and_then(|b| b.get("mimeType"))
+                .and_then(|m| m.as_str())
+                .map(|m| m.split(';').next().unwrap_or_default().trim())
+                .is_some_and(|m| SCRIPT_TYPES.contains(&m))
+        })
+        .filter_map(|a| a.get("path").and_then(|p| p.as_str()))
+        .map(|p| p.to_string())
+        .collect()
+}
+
+/// headers returns the headers site wants served along with its content, as (name, value) pairs.
+pub fn headers(site: &Value) -> Vec<(String, String)> {
+    site.get("headers")
+        .and_then(|e| e.as_array())
+        .map(|h| {
+            h.iter()
+                .filter_map(|e| {
+                    Some((
+                        e.get("name")?.as_str()?.to_string(),
+                        e.get("value")?.as_str()?.to_string(),
+                    ))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// domains returns the domains site claims to be served at.
+pub fn domains(site: &Value) -> Vec<String> {
+    site.get("domains")
+        .and_then(|e| e.as_array())
+        .map(|d| {
+            d.iter()
+                .filter_map(|e| e.as_str())
+                .map(|e| e.to_lowercase())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// error_page returns the record key of the page site wants served along with status, if any.
+pub fn error_page(site: &Value, status: u16) -> Option<String> {
+    let field = match status {
+        404 => "notFound",
+        500..=599 => "serverError",
+        _ => return None,
+    };
+
+    site.get(field)
+        .and_then(|e| e.as_str())
+        .map(|e| e.to_string())
+}
+
+/// chunked parses the chunked record with key rkey, whose chunks must add up to its size.
+pub fn chunked(value: &Value, rkey: &str) -> Result<Chunked, Error> {
+    let mime_type = value.get("mimeType").and_then(|e| e.as_str());
+    let size = value.get("size").and_then(|e| e.as_u64());
+    let chunks = value.get("chunks").and_then(|e| e.as_array()).map(|c| {
+        c.iter()
+            .map(|e| {
+                Some(Chunk {
+                    cid: link(e)?.to_string(),
+                    size: e.get("size")?.as_u64()?,
+                })
+            })
+            .collect::<Option<Vec<Chunk>>>()
+    });
+
+    match (mime_type, size, chunks) {
+        (Some(mime_type), Some(size), Some(Some(chunks)))
+            if chunks.iter().map(|c| c.size).sum::<u64>() == size =>
+        {
+            Ok(Chunked {
+                mime_type: mime_type.to_string(),
+                size,
+                chunks,
+            })
+        }
+        _ => Err(Error::MalformedRecord(rkey.to_string())),
+    }
+}
+
+/// page_content returns where the content of the page record with key rkey is.
+pub fn page_content(page: &Value, rkey: &str) -> Result<PageContent, Error> {
+    let content = page.get("content");
+
+    let cid = match content.and_then(link) {
+        Some(cid) => cid.to_string(),
+        None => return Err(Error::MalformedRecord(rkey.to_string())),
+    };
+
+    Ok(PageContent {
+        cid,
+        mime_type: content
+            .and_then(|e| e.get("mimeType"))
+            .and_then(|e| e.as_str())
+            .map(|e| e.to_string()),
+        updated_at: page
+            .get("updatedAt")
+            .and_then(|e| e.as_str())
+            .map(|e| e.to_string()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn blob(cid: &str, mime_type: &str) -> Value {
+        json!({ "$type": "blob", "ref": { "$link": cid }, "mimeType": mime_type, "size": 1 })
+    }
+
+    fn site() -> Value {
+        json!({
+            "assets": [
+                { "path": "/logo", "blob": blob("png", "image/png") },
+                { "path": "/app.js", "blob": blob("js", "text/javascript") },
+            ],
+            "pages": [
+                { "path": "/index.html", "rkey": "index" },
+                { "path": "/blog/post.html", "rkey": "post" },
+            ],
+            "blobs": [blob("page", "text/html")],
+            "headers": [{ "name": "Permissions-Policy", "value": "camera=()" }],
+        })
+    }
+
+    #[test]
+    fn finds_directory_indexes() {
+        assert_eq!(
+            path(&site(), "/").unwrap(),
+            SitePath::Page("index".to_string())
+        );
+    }
+
+    #[test]
+    fn finds_pages_without_extension() {
+        assert_eq!(
+            path(&site(), "/blog/post").unwrap(),
+            SitePath::Page("post".to_string())
+        );
+    }
+
+    #[test]
+    fn finds_assets_by_path() {
+        assert_eq!(
+            path(&site(), "/logo").unwrap(),
+            SitePath::Asset(Asset::Blob("png".to_string()))
+        );
+    }
+
+    #[test]
+    fn reports_missing_paths() {
+        assert!(matches!(
+            path(&site(), "/nope"),
+            Err(Error::NoRecordFound(_))
+        ));
+    }
+
+    #[test]
+    fn types_listed_blobs() {
+        assert_eq!(
+            stored_mime_type(&site(), "page").as_deref(),
+            Some("text/html")
+        );
+    }
+
+    #[test]
+    fn types_assets() {
+        assert_eq!(
+            stored_mime_type(&site(), "png").as_deref(),
+            Some("image/png")
+        );
+    }
+
+    #[test]
+    fn leaves_unknown_blobs_untyped() {
+        assert_eq!(stored_mime_type(&site(), "unknown"), None);
+    }
+
+    #[test]
+    fn lists_scripts() {
+        assert_eq!(scripts(&site()), vec!["/app.js".to_string()]);
+    }
+
+    #[test]
+    fn reads_headers() {
+        assert_eq!(
+            headers(&site()),
+            vec![("Permissions-Policy".to_string(), "camera=()".to_string())]
+        );
+    }
+
+    fn file() -> Chunked {
+        let chunk = |cid: &str, size| Chunk {
+            cid: cid.to_string(),
+            size,
+        };
+
+        Chunked {
+            mime_type: "video/mp4".to_string(),
+            size: 25,
+            chunks: vec![chunk("a", 10), chunk("b", 10), chunk("c", 5)],
+        }
+    }
+
+    fn part(cid: &str, start: u64, end: u64) -> Part {
+        Part {
+            cid: cid.to_string(),
+            start,
+            end,
+        }
+    }
+
+    #[test]
+    fn parts_within_one_chunk() {
+        assert_eq!(file().parts(12, 15), vec![part("b", 2, 6)]);
+    }
+
+    #[test]
+    fn parts_across_chunks() {
+        assert_eq!(
+            file().parts(5, 24),
+            vec![part("a", 5, 10), part("b", 0, 10), part("c", 0, 5)]
+        );
+    }
+
+    #[test]
+    fn parts_on_chunk_boundaries() {
+        assert_eq!(file().parts(10, 19), vec![part("b", 0, 10)]);
+    }
+
+    fn chunked_record(size: u64) -> Value {
+        let chunk = |size| json!({ "ref": { "$link": "bafkrei" }, "size": size });
+
+        json!({
+            "mimeType": "video/mp4",
+            "size": size,
+            "chunks": [chunk(10), chunk(5)],
+        })
+    }
+
+    #[test]
+    fn parses_chunked() {
+        let file = chunked(&chunked_record(15), "rkey").unwrap();
+
+        assert_eq!(
+            file.chunks.iter().map(|c| c.size).collect::<Vec<u64>>(),
+            [10, 5]
+        );
+    }
+
+    #[test]
+    fn refuses_chunks_not_adding_up() {
+        assert!(chunked(&chunked_record(16), "rkey").is_err());
+    }
+}
@@ -0,0 +1,104 @@
+use lol_html::{element, errors::RewritingError, html_content::Element, HandlerResult, Settings};
+
+/// Elements removed along with their content, since they run code or change how the rest of the
+/// page is interpreted.
+const REMOVED: &str = "script, object, embed, applet, base, meta[http-equiv]";
+
+/// Form controls, disabled so that untrusted pages can't collect anything.
+const CONTROLS: &str = "input, button, select, textarea";
+
+/// URL schemes that run code when followed.
+const SCRIPT_SCHEMES: &[&str] = &["javascript:", "vbscript:"];
+
+/// Attributes that submit or embed content on their own.
+const REMOVED_ATTRIBUTES: &[&str] = &["action", "formaction", "srcdoc"];
+
+fn settings() -> Settings<'static, 'static> {
+    Settings {
+        element_content_handlers: vec![
+            element!(REMOVED, |el| {
+                el.remove();
+                Ok(())
+            }),
+            element!(CONTROLS, |el| {
+                el.set_attribute("disabled", "")?;
+                Ok(())
+            }),
+            element!("*", strip_attributes),
+        ],
+        ..Settings::new()
+    }
+}
+
+/// strip_attributes removes event handlers and script URLs from el.
+fn strip_attributes(el: &mut Element) -> HandlerResult {
+    let unsafe_attributes = el
+        .attributes()
+        .iter()
+        .filter(|a| {
+            let name = a.name();
+
+            name.starts_with("on")
+                || REMOVED_ATTRIBUTES.contains(&name.as_str())
+                || runs_script(&a.value())
+        })
+        .map(|a| a.name())
+        .collect::<Vec<String>>();
+
+    for name in unsafe_attributes {
+        el.remove_attribute(&name);
+    }
+
+    Ok(())
+}
+
+/// runs_script returns true if url uses a scheme that runs code, ignoring the whitespace and
+/// control characters browsers ignore.
+fn runs_script(url: &str) -> bool {
+    let url = url
+        .chars()
+        .filter(|c| !c.is_ascii_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_ascii_lowercase();
+
+    SCRIPT_SCHEMES.iter().any(|s| url.starts_with(s))
+}
+
+/// html returns content without scripts, event handlers, script URLs and working forms.
+pub fn html(content: &str) -> Result<String, RewritingError> {
+    lol_html::rewrite_str(content, settings())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removes_scripts() {
+        assert_eq!(
+            html("<p>hi</p><script>steal()</script>").unwrap(),
+            "<p>hi</p>"
+        );
+    }
+
+    #[test]
+    fn removes_event_handlers() {
+        assert_eq!(html(r#"<p onclick="steal()">hi</p>"#).unwrap(), "<p>hi</p>");
+    }
+
+    #[test]
+    fn removes_script_urls() {
+        assert_eq!(
+            html(r#"<a href=" JavaScript:steal()">x</a>"#).unwrap(),
+            "<a>x</a>"
+        );
+    }
+
+    #[test]
+    fn disables_forms() {
+        assert_eq!(
+            html(r#"<form action="https://evil.example"><input></form>"#).unwrap(),
+            r#"<form><input disabled=""></form>"#
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use shared::atproto::SITE_HEADERS;
+
+/// Headers served when the site doesn't set its own.
+const DEFAULTS: &[(&str, &str)] = &[
+    ("Referrer-Policy", "strict-origin-when-cross-origin"),
+    ("X-Content-Type-Options", "nosniff"),
+    ("Cross-Origin-Opener-Policy", "same-origin"),
+];
+
+/// csp returns the default Content-Security-Policy of a repo's content served at origin:
+/// everything lives on the same origin, so scripts and workers are only allowed from the repo's
+/// own paths, lest a page pull in code published by somebody else.
+fn csp(origin: &str, own: &[String]) -> String {
+    let own = own
+        .iter()
+        .map(|path| format!("{}{}", origin, encode(path)))
+        .collect::<Vec<String>>()
+        .join(" ");
+
+    format!(
+        "script-src 'unsafe-inline' {own}; worker-src {own}; object-src 'none'; base-uri 'self'"
+    )
+}
+
+/// encode percent-encodes whatever can't appear as is in the path of a CSP source, like the ;
+/// separating directives.
+fn encode(path: &str) -> String {
+    path.bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => (b as char).to_string(),
+            b'-' | b'.' | b'_' | b'~' | b'/' | b':' | b'@' | b'!' | b'$' | b'&' | b'+' | b'=' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Policy of untrusted repos, which can't be loosened by their site record.
+const UNTRUSTED_CSP: &str =
+    "script-src 'none'; worker-src 'none'; object-src 'none'; base-uri 'none'; form-action 'none'";
+
+/// headers returns the security headers of did's content served at origin, given the ones its
+/// site record sets, falling back to the defaults for every header the site doesn't set.
+/// By default, scripts and workers are allowed from the same-origin paths in own, which are
+/// the repo's.
+/// Headers that aren't about security are ignored, a site record can't set cookies or caching.
+/// Untrusted repos always get a policy that forbids scripts, whatever their site says.
+pub fn headers(
+    origin: &str,
+    did: &str,
+    own: &[String],
+    site: Vec<(String, String)>,
+    trusted: bool,
+) -> Vec<(String, String)> {
+    let mut headers = vec![("Content-Security-Policy".to_string(), csp(origin, own))];
+    headers.extend(DEFAULTS.iter().map(|(n, v)| (n.to_string(), v.to_string())));
+
+    for (name, value) in site {
+        let name = match SITE_HEADERS.iter().find(|n| n.eq_ignore_ascii_case(&name)) {
+            Some(name) => name.to_string(),
+            None => {
+                log::debug!("{} can't set header {}", did, name);
+                continue;
+            }
+        };
+
+        headers.retain(|(n, _)| *n != name);
+        headers.push((name, value));
+    }
+
+    if !trusted {
+        headers.retain(|(n, _)| !n.starts_with("Content-Security-Policy"));
+        headers.push((
+            "Content-Security-Policy".to_string(),
+            UNTRUSTED_CSP.to_string(),
+        ));
+    }
+
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGIN: &str = "https://atpage.example";
+    const DID: &str = "did:plc:ewvi7nxzyoun6zhxrhs64oiz";
+
+    fn header(headers: &[(String, String)], name: &str) -> Option<String> {
+        headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.clone())
+    }
+
+    fn site(name: &str, value: &str) -> Vec<(String, String)> {
+        vec![(name.to_string(), value.to_string())]
+    }
+
+    fn own() -> Vec<String> {
+        vec![format!("/at/{}/", DID)]
+    }
+
+    #[test]
+    fn allows_own_scripts() {
+        let csp = header(
+            &headers(ORIGIN, DID, &own(), vec![], true),
+            "Content-Security-Policy",
+        )
+        .unwrap();
+
+        assert!(csp.contains(&format!(
+            "script-src 'unsafe-inline' {}/at/{}/",
+            ORIGIN, DID
+        )));
+    }
+
+    #[test]
+    fn encodes_own_paths() {
+        let csp = csp(ORIGIN, &["/my app;.js".to_string()]);
+
+        assert!(csp.starts_with(&format!(
+            "script-src 'unsafe-inline' {}/my%20app%3B.js;",
+            ORIGIN
+        )));
+    }
+
+    #[test]
+    fn lets_sites_set_security_headers() {
+        let h = headers(
+            ORIGIN,
+            DID,
+            &own(),
+            site("permissions-policy", "camera=()"),
+            true,
+        );
+
+        assert_eq!(
+            header(&h, "Permissions-Policy").as_deref(),
+            Some("camera=()")
+        );
+    }
+
+    #[test]
+    fn ignores_other_headers() {
+        let h = headers(
+            ORIGIN,
+            DID,
+            &own(),
+            site("Set-Cookie", "session=stolen"),
+            true,
+        );
+
+        assert_eq!(header(&h, "Set-Cookie"), None);
+    }
+
+    #[test]
+    fn forbids_scripts_of_untrusted_repos() {
+        let h = headers(
+            ORIGIN,
+            DID,
+            &own(),
+            site("Content-Security-Policy", "script-src *"),
+            false,
+        );
+
+        assert_eq!(
+            header(&h, "Content-Security-Policy").as_deref(),
+            Some(UNTRUSTED_CSP)
+        );
+    }
+}
@@ -0,0 +1,568 @@
+use crate::{
+    atproto::Error,
+    conditional::{Conditions, Validators},
+    error,
+    host::{Blob, Host, Partial},
+    identity::Identity,
+    range::{self, Range},
+    records::{self, Asset, PageContent, Part, SitePath},
+    sanitize, security, sniff,
+};
+use futures::{
+    stream::{self, Chain, Iter},
+    Stream, StreamExt,
+};
+use shared::atproto::{ATURL, CHUNKED_COLLECTION, PAGE_COLLECTION, SITE_COLLECTION, SITE_RKEY};
+use std::ops::Deref;
+
+const HTML: &str = "text/html; charset=utf-8";
+
+/// Site is what's served at plain paths.
+pub enum Site {
+    /// A handle or DID, as configured.
+    Repo(String),
+
+    /// The repo claiming the requested domain.
+    Domain(String),
+}
+
+/// Request is what serving a request depends on.
+pub struct Request {
+    /// Origin the request was sent to, which the repo served may load scripts from.
+    pub origin: String,
+
+    /// Percent-decoded path of the URL requested.
+    pub path: String,
+
+    /// Site served at plain paths, also trusted on /at/ URLs.
+    pub site: Option<Site>,
+
+    pub conditions: Conditions,
+}
+
+/// Response is what's served for a request, for each host to turn into its own kind of response.
+/// S is the stream blobs are read through on that host.
+pub struct Response<S> {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Body<S>,
+}
+
+/// Content is a blob read as it comes, its first chunk already read to sniff its type.
+pub type Content<S> = Chain<Iter<std::option::IntoIter<Result<Vec<u8>, Error>>>, S>;
+
+pub enum Body<S> {
+    Empty,
+    Bytes(Vec<u8>),
+
+    /// A blob, which errors once read if it doesn't match its CID, or a range of one as the PDS
+    /// served it.
+    Stream(Content<S>),
+
+    /// Slices of chunks of a chunked file in did's repo, to be read through chunks.
+    Chunks {
+        pds: String,
+        did: String,
+        parts: Vec<Part>,
+    },
+}
+
+impl<S> Response<S> {
+    fn new(status: u16, body: Body<S>) -> Self {
+        Response {
+            status,
+            headers: vec![],
+            body,
+        }
+    }
+
+    /// header sets the header called name, replacing any previous value.
+    fn header(mut self, name: &str, value: impl ToString) -> Self {
+        self.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+        self.headers.push((name.to_string(), value.to_string()));
+
+        self
+    }
+
+    fn validators(self, validators: &Validators) -> Self {
+        validators
+            .headers()
+            .into_iter()
+            .fold(self, |resp, (name, value)| resp.header(name, value))
+    }
+}
+
+/// Repo is the repo a request is served from.
+struct Repo {
+    did: String,
+    pds: String,
+
+    /// Handle the repo was reached by, if any.
+    handle: Option<String>,
+
+    /// Whether it's the site served at plain paths.
+    site: bool,
+
+    /// Whether its pages may run scripts.
+    trusted: bool,
+}
+
+/// route serves req, either an /at/ URL or a plain path of the site, with whatever host fetches.
+/// Failures are served as error pages.
+pub async fn route<H: Host>(host: &H, req: Request) -> Response<H::BlobStream> {
+    let res = match req.path.strip_prefix("/at/") {
+        Some(at) => match ATURL::try_from(at.to_string()) {
+            Ok(atu) => at_url(host, atu, &req).await,
+            Err(e) => Err(Error::MalformedATURL(e)),
+        },
+        None => match &req.site {
+            Some(site) => site_path(host, site, &req).await,
+            None => Err(Error::NoRecordFound(req.path.clone())),
+        },
+    };
+
+    match res {
+        Ok(r) => r,
+        Err(e) => {
+            log::error!("can't serve {}: {}", req.path, e);
+
+            let (status, body) = error::html(&e);
+            html(status, body)
+        }
+    }
+}
+
+/// chunks returns the content of parts of chunks in did's repo, fetching each chunk through host
+/// once the previous one is read, so that the whole file never sits in memory.
+pub fn chunks<H: Host, R: Deref<Target = H> + Clone>(
+    host: R,
+    pds: String,
+    did: String,
+    parts: Vec<Part>,
+) -> impl Stream<Item = Result<Vec<u8>, Error>> {
+    futures::stream::iter(parts).then(move |part| {
+        let (host, pds, did) = (host.clone(), pds.clone(), did.clone());
+
+        async move {
+            let chunk = host.blob(pds, did, part.cid.clone()).await?;
+
+            match chunk.data.get(part.start as usize..part.end as usize) {
+                Some(data) => Ok(data.to_vec()),
+                None => Err(Error::MalformedRecord(format!(
+                    "chunk {} is shorter than recorded",
+                    part.cid
+                ))),
+            }
+        }
+    })
+}
+
+async fn at_url<H: Host>(
+    host: &H,
+    atu: ATURL,
+    req: &Request,
+) -> Result<Response<H::BlobStream>, Error> {
+    let id = host.identity(&atu.did).await?;
+
+    // the site only matters here to know whether it's the repo served
+    let site = match &req.site {
+        Some(site) => match resolve(host, site).await {
+            Ok(site) => Some(site),
+            Err(e) => {
+                log::debug!("can't resolve the site: {}", e);
+                None
+            }
+        },
+        None => None,
+    };
+
+    let repo = repo(host, id, site.as_ref()).await;
+    let cond = &req.conditions;
+
+    let res = match (atu.blob, atu.asset) {
+        (true, _) => blob(host, &repo, atu.key, cond).await,
+        (_, true) => asset(host, &repo, format!("/{}", atu.key), cond).await,
+        _ if atu.collection == CHUNKED_COLLECTION => chunked(host, &repo, atu.key, cond).await,
+        _ => page(host, &repo, atu.collection, atu.key, cond).await,
+    };
+
+    secure(host, &repo, res, &req.origin).await
+}
+
+async fn site_path<H: Host>(
+    host: &H,
+    site: &Site,
+    req: &Request,
+) -> Result<Response<H::BlobStream>, Error> {
+    let id = resolve(host, site).await?;
+    let repo = repo(host, id.clone(), Some(&id)).await;
+    let cond = &req.conditions;
+
+    let path = site_record(host, &repo)
+        .await
+        .and_then(|site| records::path(&site, &req.path));
+
+    let res = match path {
+        Ok(SitePath::Page(rkey)) => {
+            page(host, &repo, PAGE_COLLECTION.to_string(), rkey, cond).await
+        }
+        Ok(SitePath::Asset(Asset::Blob(cid))) => blob(host, &repo, cid, cond).await,
+        Ok(SitePath::Asset(Asset::Chunked(rkey))) => chunked(host, &repo, rkey, cond).await,
+        Err(e) => Err(e),
+    };
+
+    secure(host, &repo, res, &req.origin).await
+}
+
+async fn resolve(host: &impl Host, site: &Site) -> Result<Identity, Error> {
+    match site {
+        Site::Repo(id) => host.identity(id).await,
+        Site::Domain(domain) => host.domain(domain).await,
+    }
+}
+
+/// repo returns the repo of id as served along with site, which is trusted.
+async fn repo(host: &impl Host, id: Identity, site: Option<&Identity>) -> Repo {
+    let is_site = site.is_some_and(|site| site.did == id.did);
+
+    let trusted = match is_site {
+        true => true,
+        false => trusted(host, &id.did).await,
+    };
+
+    // the site is often configured by handle while its pages are reached by DID
+    let handle = id
+        .handle
+        .or_else(|| site.filter(|_| is_site)?.handle.clone());
+
+    Repo {
+        did: id.did,
+        pds: id.pds,
+        handle,
+        site: is_site,
+        trusted,
+    }
+}
+
+/// trusted returns true if did is one of the trusted repos.
+async fn trusted(host: &impl Host, did: &str) -> bool {
+    for id in host.config().trusted.iter() {
+        if id == did {
+            return true;
+        }
+
+        // handles are only resolved when the DIDs listed don't match already
+        if !id.starts_with("did:") {
+            match host.identity(id).await {
+                Ok(id) if id.did == did => return true,
+                Ok(_) => {}
+                Err(e) => log::debug!("can't resolve trusted repo {}: {}", id, e),
+            }
+        }
+    }
+
+    false
+}
+
+/// secure serves the site's own page if res is an error, and sets the site's security headers on
+/// whatever is served.
+async fn secure<H: Host>(
+    host: &H,
+    repo: &Repo,
+    res: Result<Response<H::BlobStream>, Error>,
+    origin: &str,
+) -> Result<Response<H::BlobStream>, Error> {
+    let res = match res {
+        Ok(r) => r,
+        Err(e) => error_page(host, repo, e).await?,
+    };
+
+    let site = match site_record(host, repo).await {
+        Ok(site) => Some(site),
+        Err(Error::NoRecordFound(_)) => None,
+        Err(e) => {
+            log::debug!("can't read site headers of {}: {}", repo.did, e);
+            None
+        }
+    };
+
+    // pages link to their repo's files by DID, or by the handle they were published from, and
+    // the site's standalone scripts are reachable at their own plain paths too
+    let mut own = vec![format!("/at/{}/", repo.did)];
+    own.extend(repo.handle.iter().map(|h| format!("/at/{}/", h)));
+    if repo.site {
+        own.extend(site.iter().flat_map(records::scripts));
+    }
+
+    let site = site.as_ref().map(records::headers).unwrap_or_default();
+
+    Ok(
+        security::headers(origin, &repo.did, &own, site, repo.trusted)
+            .into_iter()
+            .fold(res, |res, (name, value)| res.header(&name, value)),
+    )
+}
+
+/// error_page serves the site's own page for e if it has one, and returns e otherwise.
+async fn error_page<H: Host>(
+    host: &H,
+    repo: &Repo,
+    e: Error,
+) -> Result<Response<H::BlobStream>, Error> {
+    let status = error::status(&e);
+
+    let rkey = match site_record(host, repo).await {
+        Ok(site) => records::error_page(&site, status),
+        Err(_) => None,
+    };
+
+    let rkey = match rkey {
+        Some(rkey) => rkey,
+        None => return Err(e),
+    };
+
+    let content = match page_content(host, repo, PAGE_COLLECTION.to_string(), rkey).await {
+        Ok(content) => page_body(host, repo, &content).await,
+        Err(pe) => Err(pe),
+    };
+
+    match content {
+        Ok(body) => {
+            log::error!("serving custom {} page: {}", status, e);
+
+            Ok(Response::new(status, Body::Bytes(body)).header("Content-Type", HTML))
+        }
+        Err(pe) => {
+            log::debug!("can't fetch custom {} page: {}", status, pe);
+            Err(e)
+        }
+    }
+}
+
+async fn page<H: Host>(
+    host: &H,
+    repo: &Repo,
+    collection: String,
+    rkey: String,
+    cond: &Conditions,
+) -> Result<Response<H::BlobStream>, Error> {
+    let content = page_content(host, repo, collection, rkey).await?;
+
+    let validators = Validators::page(&content.cid, content.updated_at.as_deref());
+    if cond.fresh(validators.etag()) {
+        return Ok(not_modified(&validators));
+    }
+
+    let body = page_body(host, repo, &content).await?;
+
+    Ok(Response::new(200, Body::Bytes(body))
+        .header("Content-Type", content.content_type())
+        .validators(&validators))
+}
+
+async fn page_content(
+    host: &impl Host,
+    repo: &Repo,
+    collection: String,
+    rkey: String,
+) -> Result<PageContent, Error> {
+    let page = host
+        .record(repo.pds.clone(), repo.did.clone(), collection, rkey.clone())
+        .await?;
+
+    records::page_content(&page, &rkey)
+}
+
+/// page_body returns the content of a page, sanitized unless its repo is trusted.
+/// Pages are read whole, so that they're checked against their CID before the browser runs any
+/// of them.
+async fn page_body(host: &impl Host, repo: &Repo, content: &PageContent) -> Result<Vec<u8>, Error> {
+    let blob = host
+        .blob(repo.pds.clone(), repo.did.clone(), content.cid.clone())
+        .await?;
+
+    match repo.trusted {
+        true => Ok(blob.data),
+        false => sanitize::html(&String::from_utf8_lossy(&blob.data))
+            .map(|c| c.into_bytes())
+            .map_err(|e| Error::MalformedRecord(format!("can't sanitize page: {}", e))),
+    }
+}
+
+async fn asset<H: Host>(
+    host: &H,
+    repo: &Repo,
+    path: String,
+    cond: &Conditions,
+) -> Result<Response<H::BlobStream>, Error> {
+    match records::asset(&site_record(host, repo).await?, &path) {
+        Some(Ok(Asset::Blob(cid))) => blob(host, repo, cid, cond).await,
+        Some(Ok(Asset::Chunked(rkey))) => chunked(host, repo, rkey, cond).await,
+        Some(Err(e)) => Err(e),
+        None => Err(Error::NoAssetFound(path)),
+    }
+}
+
+/// blob serves the blob cid whole, streamed as it's read, or the range asked for.
+async fn blob<H: Host>(
+    host: &H,
+    repo: &Repo,
+    cid: String,
+    cond: &Conditions,
+) -> Result<Response<H::BlobStream>, Error> {
+    let validators = Validators::content(&cid);
+    if cond.fresh(validators.etag()) {
+        return Ok(not_modified(&validators));
+    }
+
+    // PDSes often serve every blob as application/octet-stream: what the publisher recorded
+    // wins, and the content itself comes second
+    let stored = match site_record(host, repo).await {
+        Ok(site) => records::stored_mime_type(&site, &cid),
+        Err(_) => None,
+    };
+
+    let resp = match cond.range {
+        None => {
+            let blob = host
+                .blob_stream(repo.pds.clone(), repo.did.clone(), cid.clone())
+                .await?;
+
+            let (head, rest) = blob.body.into_future().await;
+            let head = head.transpose()?;
+
+            let data = head.as_deref().unwrap_or_default();
+            let mime_type = sniff::or_sniff(stored, data, blob.mime_type);
+
+            Response::new(200, Body::Stream(stream::iter(head.map(Ok)).chain(rest)))
+                .header("Content-Type", mime_type)
+        }
+        // ranges can't be checked against the CID, only blobs kept whole are sliced here
+        Some(range) => match host.cached_blob(&cid).await {
+            Some(blob) => slice(blob, range, stored),
+            None => match host
+                .blob_range(repo.pds.clone(), repo.did.clone(), cid.clone(), range)
+                .await?
+            {
+                Some(part) => partial(part, stored),
+                None => {
+                    let blob = host
+                        .blob(repo.pds.clone(), repo.did.clone(), cid.clone())
+                        .await?;
+
+                    slice(blob, range, stored)
+                }
+            },
+        },
+    };
+
+    Ok(resp
+        .header("Accept-Ranges", "bytes")
+        .validators(&validators))
+}
+
+/// slice serves range of blob, which was checked against its CID.
+fn slice<S>(blob: Blob, range: Range, stored: Option<String>) -> Response<S> {
+    let mime_type = sniff::or_sniff(stored, &blob.data, blob.mime_type);
+    let size = blob.data.len() as u64;
+
+    let resp = match range.resolve(size) {
+        Some((start, end)) => Response::new(
+            206,
+            Body::Bytes(blob.data[start as usize..=end as usize].to_vec()),
+        )
+        .header("Content-Range", range::content_range(start, end, size)),
+        None => unsatisfiable(size),
+    };
+
+    resp.header("Content-Type", mime_type)
+}
+
+/// partial serves part, a range of a blob as the PDS served it.
+fn partial<S: Stream<Item = Result<Vec<u8>, Error>>>(
+    part: Partial<S>,
+    stored: Option<String>,
+) -> Response<S> {
+    // the start of the blob isn't there to sniff
+    let mime_type = sniff::or_sniff(stored, &[], part.mime_type);
+
+    let resp = match part.status {
+        206 => Response::new(206, Body::Stream(stream::iter(None).chain(part.body))),
+        status => Response::new(status, Body::Empty),
+    };
+
+    let resp = match part.content_range {
+        Some(content_range) => resp.header("Content-Range", content_range),
+        None => resp,
+    };
+
+    resp.header("Content-Type", mime_type)
+}
+
+/// chunked serves the chunked file at rkey whole, or the range asked for, reading only the
+/// chunks needed.
+async fn chunked<H: Host>(
+    host: &H,
+    repo: &Repo,
+    rkey: String,
+    cond: &Conditions,
+) -> Result<Response<H::BlobStream>, Error> {
+    // chunked records are keyed by the CID of the whole file
+    let validators = Validators::content(&rkey);
+    if cond.fresh(validators.etag()) {
+        return Ok(not_modified(&validators));
+    }
+
+    let record = host
+        .record(
+            repo.pds.clone(),
+            repo.did.clone(),
+            CHUNKED_COLLECTION.to_string(),
+            rkey.clone(),
+        )
+        .await?;
+    let file = records::chunked(&record, &rkey)?;
+
+    let body = |parts| Body::Chunks {
+        pds: repo.pds.clone(),
+        did: repo.did.clone(),
+        parts,
+    };
+
+    let resp = match cond.range.map(|r| r.resolve(file.size)) {
+        None => Response::new(200, body(file.parts(0, file.size.saturating_sub(1))))
+            .header("Content-Length", file.size),
+        Some(Some((start, end))) => Response::new(206, body(file.parts(start, end)))
+            .header("Content-Range", range::content_range(start, end, file.size))
+            .header("Content-Length", end - start + 1),
+        Some(None) => unsatisfiable(file.size),
+    };
+
+    Ok(resp
+        .header("Content-Type", file.mime_type)
+        .header("Accept-Ranges", "bytes")
+        .validators(&validators))
+}
+
+async fn site_record(host: &impl Host, repo: &Repo) -> Result<serde_json::Value, Error> {
+    host.record(
+        repo.pds.clone(),
+        repo.did.clone(),
+        SITE_COLLECTION.to_string(),
+        SITE_RKEY.to_string(),
+    )
+    .await
+}
+
+/// not_modified tells the client its copy is still good.
+fn not_modified<S>(validators: &Validators) -> Response<S> {
+    Response::new(304, Body::Empty).validators(validators)
+}
+
+fn unsatisfiable<S>(size: u64) -> Response<S> {
+    Response::new(416, Body::Empty).header("Content-Range", format!("bytes */{}", size))
+}
+
+fn html<S>(status: u16, body: String) -> Response<S> {
+    Response::new(status, Body::Bytes(body.into_bytes())).header("Content-Type", HTML)
+}
@@ -0,0 +1,97 @@
+const FALLBACK: &str = "application/octet-stream";
+
+/// Signatures of the formats websites commonly serve, as (offset, magic bytes, MIME type).
+const SIGNATURES: &[(usize, &[u8], &str)] = &[
+    (0, b"\x89PNG\r\n\x1a\n", "image/png"),
+    (0, b"\xff\xd8\xff", "image/jpeg"),
+    (0, b"GIF87a", "image/gif"),
+    (0, b"GIF89a", "image/gif"),
+    (0, b"\x00\x00\x01\x00", "image/x-icon"),
+    (0, b"%PDF-", "application/pdf"),
+    (0, b"wOFF", "font/woff"),
+    (0, b"wOF2", "font/woff2"),
+    (0, b"\x1a\x45\xdf\xa3", "video/webm"),
+    (0, b"OggS", "audio/ogg"),
+    (0, b"ID3", "audio/mpeg"),
+    (0, b"fLaC", "audio/flac"),
+    (4, b"ftyp", "video/mp4"),
+    (0, b"\x1f\x8b", "application/gzip"),
+    (0, b"PK\x03\x04", "application/zip"),
+    (0, b"\x00asm", "application/wasm"),
+];
+
+/// RIFF containers carry their actual format at offset 8.
+const RIFF: &[(&[u8], &str)] = &[(b"WEBP", "image/webp"), (b"WAVE", "audio/wav")];
+
+/// Markup sniffed from the first non-whitespace bytes, compared case-insensitively.
+const MARKUP: &[(&str, &str)] = &[
+    ("<?xml", "application/xml"),
+    ("<svg", "image/svg+xml"),
+    ("<!doctype html", "text/html"),
+    ("<html", "text/html"),
+];
+
+/// sniff guesses the MIME type of content starting with data, or returns None when it has
+/// no idea.
+pub fn sniff(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"RIFF") {
+        return RIFF
+            .iter()
+            .find(|(magic, _)| data.get(8..12) == Some(magic))
+            .map(|(_, mime)| *mime);
+    }
+
+    if let Some((_, _, mime)) = SIGNATURES
+        .iter()
+        .find(|(offset, magic, _)| data.get(*offset..offset + magic.len()) == Some(magic))
+    {
+        return Some(mime);
+    }
+
+    let text = String::from_utf8_lossy(&data[..data.len().min(512)]);
+    let start = text.trim_start().to_lowercase();
+
+    // an SVG file may well start with an XML declaration
+    if start.starts_with("<?xml") && start.contains("<svg") {
+        return Some("image/svg+xml");
+    }
+
+    MARKUP
+        .iter()
+        .find(|(prefix, _)| start.starts_with(prefix))
+        .map(|(_, mime)| *mime)
+}
+
+/// or_sniff returns stored if set, or sniffs data otherwise.
+pub fn or_sniff(stored: Option<String>, data: &[u8], fallback: Option<String>) -> String {
+    stored
+        .or(sniff(data).map(|m| m.to_string()))
+        .or(fallback)
+        .unwrap_or(FALLBACK.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniffs_signatures() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), Some("image/png"));
+    }
+
+    #[test]
+    fn prefers_stored_types() {
+        assert_eq!(
+            or_sniff(Some("text/css".to_string()), b"GIF89a", None),
+            "text/css"
+        );
+    }
+
+    #[test]
+    fn falls_back_to_served_types() {
+        assert_eq!(
+            or_sniff(None, b"plain", Some("text/plain".to_string())),
+            "text/plain"
+        );
+    }
+}
@@ -0,0 +1,504 @@
+use crate::{
+    atproto::{self, Error},
+    did,
+    host::Host,
+    ipld::{self, Car, Cid, Value},
+};
+use futures::{Stream, StreamExt};
+use sha2::{Digest, Sha256};
+use std::cmp::Ordering;
+
+const SECP256K1_PREFIX: [u8; 2] = [0xe7, 0x01];
+const P256_PREFIX: [u8; 2] = [0x80, 0x24];
+
+/// PublicKey is a repo signing key, as listed in a DID document.
+enum PublicKey {
+    K256(k256::ecdsa::VerifyingKey),
+    P256(p256::ecdsa::VerifyingKey),
+}
+
+impl PublicKey {
+    /// from_multibase decodes a Multikey-encoded public key.
+    fn from_multibase(key: &str) -> Result<Self, Error> {
+        let (_, bytes) = multibase::decode(key)
+            .map_err(|e| Error::VerificationFailed(format!("malformed signing key: {}", e)))?;
+
+        let malformed = |e| Error::VerificationFailed(format!("malformed signing key: {}", e));
+
+        match (bytes.get(..2), bytes.get(2..)) {
+            (Some(p), Some(k)) if p == SECP256K1_PREFIX => Ok(PublicKey::K256(
+                k256::ecdsa::VerifyingKey::from_sec1_bytes(k).map_err(malformed)?,
+            )),
+            (Some(p), Some(k)) if p == P256_PREFIX => Ok(PublicKey::P256(
+                p256::ecdsa::VerifyingKey::from_sec1_bytes(k).map_err(malformed)?,
+            )),
+            _ => Err(Error::VerificationFailed(
+                "unsupported signing key type".to_string(),
+            )),
+        }
+    }
+
+    /// verify checks that sig is a low-S signature of msg.
+    fn verify(&self, msg: &[u8], sig: &[u8]) -> Result<(), Error> {
+        use k256::ecdsa::signature::Verifier;
+
+        let invalid = |e| Error::VerificationFailed(format!("invalid commit signature: {}", e));
+
+        let high_s = match self {
+            PublicKey::K256(k) => {
+                let sig = k256::ecdsa::Signature::from_slice(sig).map_err(invalid)?;
+                k.verify(msg, &sig).map_err(invalid)?;
+                sig.normalize_s().is_some()
+            }
+            PublicKey::P256(k) => {
+                let sig = p256::ecdsa::Signature::from_slice(sig).map_err(invalid)?;
+                k.verify(msg, &sig).map_err(invalid)?;
+                sig.normalize_s().is_some()
+            }
+        };
+
+        match high_s {
+            true => Err(Error::VerificationFailed(
+                "commit signature is not in low-S form".to_string(),
+            )),
+            false => Ok(()),
+        }
+    }
+}
+
+/// record fetches the record at collection/rkey in did's repo along with its proof, and returns its
+/// value only if the commit is signed by did's signing key and its MST includes the record.
+pub async fn record(
+    host: &impl Host,
+    pds: String,
+    did: String,
+    collection: String,
+    rkey: String,
+) -> Result<atproto::Record, Error> {
+    let uri = format!("at://{}/{}/{}", did, collection, rkey);
+
+    let car = atproto::car(host, pds, did.clone(), collection.clone(), rkey.clone()).await?;
+    let car = Car::read(&car)?;
+
+    let key = signing_key(host, did.clone()).await?;
+
+    let commit_cid = car
+        .roots
+        .first()
+        .ok_or(Error::VerificationFailed(format!("no commit for {}", uri)))?;
+    let commit = ipld::decode(block(&car, commit_cid)?)?;
+
+    verify_commit(&commit, &did, &key)?;
+
+    let data = commit
+        .get("data")
+        .and_then(|d| d.as_link())
+        .ok_or(Error::VerificationFailed(format!(
+            "no MST root for {}",
+            uri
+        )))?;
+
+    let record_cid = match mst_lookup(&car, data, format!("{}/{}", collection, rkey).as_bytes())? {
+        Some(c) => c,
+        None => return Err(Error::NoRecordFound(uri)),
+    };
+
+    let record = ipld::decode(block(&car, &record_cid)?)?;
+
+    log::debug!("verified record {} at {}", uri, record_cid);
+
+    Ok(atproto::Record {
+        cid: record_cid.to_string(),
+        value: record.to_json(),
+    })
+}
+
+/// blob checks that data hashes to cid.
+pub fn blob(cid: &str, data: &[u8]) -> Result<(), Error> {
+    match Cid::parse(cid)?.matches(data) {
+        true => Ok(()),
+        false => Err(Error::VerificationFailed(format!(
+            "blob content doesn't match CID {}",
+            cid
+        ))),
+    }
+}
+
+/// blob_stream passes through the chunks of stream, the content of the blob cid, hashing them as
+/// they go by, and ends it with an error if its content doesn't match cid.
+/// Readers get to see content before it's checked, but never a cleanly ended stream of bad content.
+pub fn blob_stream(
+    cid: Cid,
+    stream: impl Stream<Item = Result<Vec<u8>, Error>>,
+) -> impl Stream<Item = Result<Vec<u8>, Error>> {
+    futures::stream::unfold(
+        (Box::pin(stream), Some(Sha256::new())),
+        move |(mut stream, hasher)| {
+            let cid = cid.clone();
+            async move {
+                // the hasher is gone once the stream ended or errored
+                let mut hasher = hasher?;
+
+                match stream.next().await {
+                    Some(Ok(chunk)) => {
+                        hasher.update(&chunk);
+                        Some((Ok(chunk), (stream, Some(hasher))))
+                    }
+                    Some(Err(e)) => Some((Err(e), (stream, None))),
+                    None => match cid.matches_digest(&hasher.finalize()) {
+                        true => None,
+                        false => Some((
+                            Err(Error::VerificationFailed(format!(
+                                "blob content doesn't match CID {}",
+                                cid
+                            ))),
+                            (stream, None),
+                        )),
+                    },
+                }
+            }
+        },
+    )
+}
+
+async fn signing_key(host: &impl Host, did: String) -> Result<PublicKey, Error> {
+    let doc = did::resolve(host, did.clone()).await?;
+
+    let key = doc.signing_key().ok_or(Error::VerificationFailed(format!(
+        "no signing key for {}",
+        did
+    )))?;
+
+    PublicKey::from_multibase(key)
+}
+
+fn block<'a>(car: &'a Car, cid: &Cid) -> Result<&'a [u8], Error> {
+    car.block(cid).ok_or(Error::VerificationFailed(format!(
+        "block {} missing from proof",
+        cid
+    )))
+}
+
+fn verify_commit(commit: &Value, did: &str, key: &PublicKey) -> Result<(), Error> {
+    if commit.get("did").and_then(|d| d.as_str()) != Some(did) {
+        return Err(Error::VerificationFailed(format!(
+            "commit doesn't belong to {}",
+            did
+        )));
+    }
+
+    let sig = commit
+        .get("sig")
+        .and_then(|s| s.as_bytes())
+        .ok_or(Error::VerificationFailed("unsigned commit".to_string()))?;
+
+    // the signature covers the DAG-CBOR encoding of the commit without its sig field
+    let mut unsigned = commit.clone();
+    if let Value::Map(m) = &mut unsigned {
+        m.remove("sig");
+    }
+
+    key.verify(&ipld::encode(&unsigned), sig)
+}
+
+/// mst_lookup walks the MST rooted at root looking for key, returning the CID of the record it
+/// points to, or None if the MST proves there's no such key.
+fn mst_lookup(car: &Car, root: &Cid, key: &[u8]) -> Result<Option<Cid>, Error> {
+    let malformed = |what: &str| Error::VerificationFailed(format!("malformed MST node: {}", what));
+
+    let mut node_cid = root.clone();
+    loop {
+        let node = ipld::decode(block(car, &node_cid)?)?;
+
+        let entries = node
+            .get("e")
+            .and_then(|e| e.as_list())
+            .ok_or(malformed("no entries"))?;
+
+        // subtree holds the keys between the previous entry and the current one
+        let mut subtree = node.get("l").and_then(|l| l.as_link()).cloned();
+        let mut prev_key: Vec<u8> = vec![];
+
+        for e in entries {
+            let prefix_len = e
+                .get("p")
+                .and_then(|p| p.as_integer())
+                .ok_or(malformed("no prefix length"))? as usize;
+            let suffix = e
+                .get("k")
+                .and_then(|k| k.as_bytes())
+                .ok_or(malformed("no key suffix"))?;
+
+            let mut full_key = prev_key
+                .get(..prefix_len)
+                .ok_or(malformed("prefix longer than previous key"))?
+                .to_vec();
+            full_key.extend_from_slice(suffix);
+
+            match key.cmp(&full_key) {
+                Ordering::Equal => {
+                    return Ok(Some(
+                        e.get("v")
+                            .and_then(|v| v.as_link())
+                            .ok_or(malformed("no value"))?
+                            .clone(),
+                    ))
+                }
+                Ordering::Less => break,
+                Ordering::Greater => {
+                    subtree = e.get("t").and_then(|t| t.as_link()).cloned();
+                    prev_key = full_key;
+                }
+            }
+        }
+
+        match subtree {
+            Some(c) => node_cid = c,
+            None => return Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipld::tests::{car, cid};
+    use k256::ecdsa::{signature::Signer, SigningKey};
+
+    const DID: &str = "did:plc:ewvi7nxzyoun6zhxrhs64oiz";
+    const KEY: &[u8] = b"industries.geesawra.atpage/post";
+
+    fn map(entries: Vec<(&str, Value)>) -> Value {
+        Value::Map(
+            entries
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+        )
+    }
+
+    fn signing_key(seed: u8) -> SigningKey {
+        SigningKey::from_slice(&[seed; 32]).unwrap()
+    }
+
+    fn public_key(key: &SigningKey) -> PublicKey {
+        PublicKey::K256(*key.verifying_key())
+    }
+
+    fn record() -> Vec<u8> {
+        ipld::encode(&map(vec![("title", Value::String("Hello".to_string()))]))
+    }
+
+    /// mst returns an MST node holding the record at KEY, after an entry sharing most of its key.
+    fn mst() -> Vec<u8> {
+        let entry = |p: usize, k: &[u8], v: &[u8]| {
+            map(vec![
+                ("p", Value::Integer(p as i128)),
+                ("k", Value::Bytes(k.to_vec())),
+                ("v", Value::Link(cid(v))),
+                ("t", Value::Null),
+            ])
+        };
+
+        ipld::encode(&map(vec![
+            ("l", Value::Null),
+            (
+                "e",
+                Value::List(vec![
+                    entry(0, b"industries.geesawra.atpage/index", b"index"),
+                    entry(27, b"post", &record()),
+                ]),
+            ),
+        ]))
+    }
+
+    /// commit returns the commit of DID pointing to the MST, signed with sign.
+    fn commit(sign: impl Fn(&[u8]) -> Vec<u8>) -> Value {
+        let mut commit = map(vec![
+            ("did", Value::String(DID.to_string())),
+            ("version", Value::Integer(3)),
+            ("data", Value::Link(cid(&mst()))),
+            ("rev", Value::String("3l2mqzj7iqk2a".to_string())),
+            ("prev", Value::Null),
+        ]);
+
+        let sig = sign(&ipld::encode(&commit));
+        if let Value::Map(m) = &mut commit {
+            m.insert("sig".to_string(), Value::Bytes(sig));
+        }
+
+        commit
+    }
+
+    fn signed_by(key: SigningKey) -> impl Fn(&[u8]) -> Vec<u8> {
+        move |msg| {
+            let sig: k256::ecdsa::Signature = key.sign(msg);
+            sig.to_bytes().to_vec()
+        }
+    }
+
+    /// proof returns the CAR file a PDS serves for the record at KEY.
+    fn proof() -> Car {
+        let commit = ipld::encode(&commit(signed_by(signing_key(1))));
+
+        Car::read(&car(&cid(&commit), &[&commit, &mst(), &record()])).unwrap()
+    }
+
+    #[test]
+    fn accepts_valid_proofs() {
+        let car = proof();
+
+        let commit = ipld::decode(block(&car, &car.roots[0]).unwrap()).unwrap();
+        verify_commit(&commit, DID, &public_key(&signing_key(1))).unwrap();
+
+        let data = commit.get("data").and_then(|d| d.as_link()).unwrap();
+        assert_eq!(mst_lookup(&car, data, KEY).unwrap(), Some(cid(&record())));
+    }
+
+    #[test]
+    fn proves_missing_keys() {
+        let car = proof();
+
+        assert_eq!(
+            mst_lookup(&car, &cid(&mst()), b"industries.geesawra.atpage/other").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn refuses_proofs_missing_blocks() {
+        let commit = ipld::encode(&commit(signed_by(signing_key(1))));
+        let car = Car::read(&car(&cid(&commit), &[&commit, &record()])).unwrap();
+
+        assert!(matches!(
+            mst_lookup(&car, &cid(&mst()), KEY),
+            Err(Error::VerificationFailed(_))
+        ));
+    }
+
+    #[test]
+    fn refuses_signatures_of_other_keys() {
+        let commit = commit(signed_by(signing_key(2)));
+
+        assert!(matches!(
+            verify_commit(&commit, DID, &public_key(&signing_key(1))),
+            Err(Error::VerificationFailed(_))
+        ));
+    }
+
+    #[test]
+    fn refuses_commits_of_other_repos() {
+        let commit = commit(signed_by(signing_key(1)));
+
+        assert!(matches!(
+            verify_commit(&commit, "did:plc:someoneelse", &public_key(&signing_key(1))),
+            Err(Error::VerificationFailed(_))
+        ));
+    }
+
+    #[test]
+    fn refuses_high_s_k256_signatures() {
+        let key = signing_key(1);
+
+        // negating s keeps the signature valid, but in the form atproto forbids
+        let commit = commit(|msg| {
+            let sig: k256::ecdsa::Signature = key.sign(msg);
+            let (r, s) = sig.split_scalars();
+            k256::ecdsa::Signature::from_scalars(r, -s)
+                .unwrap()
+                .to_bytes()
+                .to_vec()
+        });
+
+        assert!(matches!(
+            verify_commit(&commit, DID, &public_key(&key)),
+            Err(Error::VerificationFailed(_))
+        ));
+    }
+
+    #[test]
+    fn refuses_high_s_p256_signatures() {
+        let key = p256::ecdsa::SigningKey::from_slice(&[1; 32]).unwrap();
+
+        // unlike k256, p256 accepts high-S signatures on its own
+        let commit = commit(|msg| {
+            let sig: p256::ecdsa::Signature = key.sign(msg);
+            let (r, s) = sig.split_scalars();
+            p256::ecdsa::Signature::from_scalars(r, -s)
+                .unwrap()
+                .to_bytes()
+                .to_vec()
+        });
+
+        let e = verify_commit(&commit, DID, &PublicKey::P256(*key.verifying_key()))
+            .err()
+            .unwrap();
+        assert!(e.to_string().contains("low-S"));
+    }
+
+    #[test]
+    fn refuses_unsigned_commits() {
+        let mut commit = commit(signed_by(signing_key(1)));
+        if let Value::Map(m) = &mut commit {
+            m.remove("sig");
+        }
+
+        assert!(matches!(
+            verify_commit(&commit, DID, &public_key(&signing_key(1))),
+            Err(Error::VerificationFailed(_))
+        ));
+    }
+
+    #[test]
+    fn reads_multikeys() {
+        let mut bytes = SECP256K1_PREFIX.to_vec();
+        bytes.extend_from_slice(
+            signing_key(1)
+                .verifying_key()
+                .to_encoded_point(true)
+                .as_bytes(),
+        );
+        let key = multibase::encode(multibase::Base::Base58Btc, bytes);
+
+        assert!(matches!(
+            PublicKey::from_multibase(&key),
+            Ok(PublicKey::K256(_))
+        ));
+    }
+
+    #[test]
+    fn refuses_unsupported_keys() {
+        let key = multibase::encode(multibase::Base::Base58Btc, [0xed, 0x01, 1, 2, 3]);
+
+        assert!(matches!(
+            PublicKey::from_multibase(&key),
+            Err(Error::VerificationFailed(_))
+        ));
+    }
+
+    /// read returns everything stream yields, split in chunks, once checked against cid.
+    fn read(cid: Cid, chunks: &[&[u8]]) -> Result<Vec<u8>, Error> {
+        let stream = futures::stream::iter(chunks.iter().map(|c| Ok(c.to_vec())));
+
+        futures::executor::block_on(blob_stream(cid, stream).collect::<Vec<_>>())
+            .into_iter()
+            .collect::<Result<Vec<Vec<u8>>, Error>>()
+            .map(|c| c.concat())
+    }
+
+    #[test]
+    fn streams_matching_blobs() {
+        assert_eq!(
+            read(cid(b"hello world"), &[b"hello", b" world"]).unwrap(),
+            b"hello world"
+        );
+    }
+
+    #[test]
+    fn ends_tampered_blobs_with_an_error() {
+        assert!(matches!(
+            read(cid(b"hello world"), &[b"hello", b" there"]),
+            Err(Error::VerificationFailed(_))
+        ));
+    }
+}
@@ -0,0 +1,219 @@
+//! A mock PDS, PLC directory and DNS-over-HTTPS resolver, all served by the same local server.
+
+#![allow(dead_code)]
+
+use atpage_renderer::{config::Config, host::Native};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use wiremock::{
+    matchers::{method, path, query_param},
+    Mock, MockServer, Request, ResponseTemplate,
+};
+
+pub const DID: &str = "did:plc:ewvi7nxzyoun6zhxrhs64oiz";
+pub const HANDLE: &str = "alice.test";
+
+/// Services is a running mock of the services atpage talks to.
+pub struct Services {
+    pub server: MockServer,
+}
+
+impl Services {
+    pub async fn start() -> Self {
+        Services {
+            server: MockServer::start().await,
+        }
+    }
+
+    /// host returns a native host talking to the mock only, web servers included.
+    pub fn host(&self) -> Native {
+        self.host_with(Config::default())
+    }
+
+    /// host_with returns a native host configured with config, talking to the mock only.
+    pub fn host_with(&self, config: Config) -> Native {
+        Native::new(Config {
+            plc_directory: self.server.uri(),
+            doh_resolver: format!("{}/dns-query", self.server.uri()),
+            ..config
+        })
+        .with_web(self.server.uri())
+    }
+
+    /// did serves the DID document of did in the PLC directory, pointing to the mock PDS and
+    /// claiming handle.
+    pub async fn did(&self, did: &str, handle: &str) {
+        let doc = json!({
+            "id": did,
+            "alsoKnownAs": [format!("at://{}", handle)],
+            "verificationMethod": [],
+            "service": [{
+                "id": "#atproto_pds",
+                "type": "AtprotoPersonalDataServer",
+                "serviceEndpoint": self.server.uri(),
+            }],
+        });
+
+        Mock::given(method("GET"))
+            .and(path(format!("/{}", did)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(doc))
+            .mount(&self.server)
+            .await;
+    }
+
+    /// handle serves the _atproto TXT records of handle, one for each of dids.
+    pub async fn handle(&self, handle: &str, dids: &[&str]) {
+        self.txt(&format!("_atproto.{}", handle), dids).await
+    }
+
+    /// domain serves the _atpage TXT record of domain, pointing to did.
+    pub async fn domain(&self, domain: &str, did: &str) {
+        self.txt(&format!("_atpage.{}", domain), &[did]).await
+    }
+
+    /// txt serves the "did=" TXT records of name, one for each of dids.
+    async fn txt(&self, name: &str, dids: &[&str]) {
+        let answer = dids
+            .iter()
+            .map(|d| {
+                json!({
+                    "name": name,
+                    "type": 16,
+                    "data": format!("\"did={}\"", d),
+                })
+            })
+            .collect::<Vec<Value>>();
+
+        Mock::given(method("GET"))
+            .and(path("/dns-query"))
+            .and(query_param("name", name))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "Answer": answer })))
+            .mount(&self.server)
+            .await;
+    }
+
+    /// web serves body at path on the web server of domain.
+    pub async fn web(&self, domain: &str, p: &str, body: &str) {
+        Mock::given(method("GET"))
+            .and(path(format!("/{}{}", domain, p)))
+            .respond_with(ResponseTemplate::new(200).set_body_string(body))
+            .mount(&self.server)
+            .await;
+    }
+
+    /// record serves value as the record at collection/rkey in did's repo.
+    pub async fn record(&self, did: &str, collection: &str, rkey: &str, value: Value) {
+        Mock::given(method("GET"))
+            .and(path("/xrpc/com.atproto.repo.getRecord"))
+            .and(query_param("repo", did))
+            .and(query_param("collection", collection))
+            .and(query_param("rkey", rkey))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "uri": format!("at://{}/{}/{}", did, collection, rkey),
+                "cid": cid(value.to_string().as_bytes()),
+                "value": value,
+            })))
+            .mount(&self.server)
+            .await;
+    }
+
+    /// blob serves data as the blob cid in did's repo.
+    pub async fn blob(&self, did: &str, cid: &str, data: &[u8]) {
+        Mock::given(method("GET"))
+            .and(path("/xrpc/com.atproto.sync.getBlob"))
+            .and(query_param("did", did))
+            .and(query_param("cid", cid))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Content-Type", "application/octet-stream")
+                    .set_body_bytes(data.to_vec()),
+            )
+            .mount(&self.server)
+            .await;
+    }
+
+    /// blob_ranges serves data as the blob cid in did's repo, answering single "bytes=start-end"
+    /// ranges with just that part, like most PDSes do.
+    pub async fn blob_ranges(&self, did: &str, cid: &str, data: &[u8]) {
+        let data = data.to_vec();
+
+        Mock::given(method("GET"))
+            .and(path("/xrpc/com.atproto.sync.getBlob"))
+            .and(query_param("did", did))
+            .and(query_param("cid", cid))
+            .respond_with(move |req: &Request| {
+                let range = req
+                    .headers
+                    .get("Range")
+                    .and_then(|r| r.to_str().ok()?.strip_prefix("bytes=")?.split_once('-'))
+                    .and_then(|(s, e)| Some((s.parse::<usize>().ok()?, e.parse::<usize>().ok()?)));
+
+                let resp = ResponseTemplate::new(200);
+                let resp = match range {
+                    None => resp.set_body_bytes(data.clone()),
+                    Some((start, _)) if start >= data.len() => ResponseTemplate::new(416)
+                        .insert_header("Content-Range", format!("bytes */{}", data.len())),
+                    Some((start, end)) => {
+                        let end = end.min(data.len() - 1);
+
+                        ResponseTemplate::new(206)
+                            .insert_header(
+                                "Content-Range",
+                                format!("bytes {}-{}/{}", start, end, data.len()),
+                            )
+                            .set_body_bytes(data[start..=end].to_vec())
+                    }
+                };
+
+                resp.insert_header("Content-Type", "application/octet-stream")
+            })
+            .mount(&self.server)
+            .await;
+    }
+
+    /// failing answers requests for p like a server behind a failing proxy, with an HTML page,
+    /// whatever else was mounted for it.
+    pub async fn failing(&self, p: &str) {
+        Mock::given(method("GET"))
+            .and(path(p))
+            .respond_with(
+                ResponseTemplate::new(500)
+                    .insert_header("Content-Type", "text/html")
+                    .set_body_string("<html><body>Internal Server Error</body></html>"),
+            )
+            .with_priority(1)
+            .mount(&self.server)
+            .await;
+    }
+
+    /// not_found answers any XRPC request nothing was mounted for like a PDS missing the record.
+    pub async fn not_found(&self) {
+        Mock::given(method("GET"))
+            .and(path("/xrpc/com.atproto.repo.getRecord"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "error": "RecordNotFound",
+                "message": "Could not locate record",
+            })))
+            .with_priority(u8::MAX)
+            .mount(&self.server)
+            .await;
+    }
+}
+
+/// cid returns the CID of a raw blob holding data.
+pub fn cid(data: &[u8]) -> String {
+    let mut bytes = vec![0x01, 0x55, 0x12, 0x20];
+    bytes.extend_from_slice(&Sha256::digest(data));
+
+    multibase::encode(multibase::Base::Base32Lower, bytes)
+}
+
+/// blob_ref returns the JSON blob reference to data, stored as mime_type.
+pub fn blob_ref(data: &[u8], mime_type: &str) -> Value {
+    json!({
+        "$type": "blob",
+        "ref": { "$link": cid(data) },
+        "mimeType": mime_type,
+        "size": data.len(),
+    })
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use atpage_renderer::{atproto::Error, domain};
+use common::{Services, DID, HANDLE};
+use serde_json::json;
+use shared::atproto::{SITE_COLLECTION, SITE_RKEY};
+
+const DOMAIN: &str = "site.alice.test";
+
+#[tokio::test]
+async fn resolves_claimed_domain() {
+    let s = Services::start().await;
+    s.did(DID, HANDLE).await;
+    s.domain(DOMAIN, DID).await;
+    s.record(
+        DID,
+        SITE_COLLECTION,
+        SITE_RKEY,
+        json!({ "assets": [], "pages": [], "domains": [DOMAIN] }),
+    )
+    .await;
+
+    let id = domain::solve(&s.host(), "Site.Alice.Test").await.unwrap();
+
+    assert_eq!(id.did, DID);
+    assert_eq!(id.pds, s.server.uri());
+}
+
+#[tokio::test]
+async fn refuses_domain_not_claimed_back() {
+    let s = Services::start().await;
+    s.did(DID, HANDLE).await;
+    s.domain(DOMAIN, DID).await;
+    s.record(
+        DID,
+        SITE_COLLECTION,
+        SITE_RKEY,
+        json!({ "assets": [], "pages": [], "domains": ["elsewhere.example"] }),
+    )
+    .await;
+
+    let res = domain::solve(&s.host(), DOMAIN).await;
+
+    assert!(matches!(res, Err(Error::UnclaimedDomain(_))));
+}
+
+#[tokio::test]
+async fn refuses_unclaimed_domain() {
+    let s = Services::start().await;
+
+    let res = domain::solve(&s.host(), "nobody.test").await;
+
+    assert!(matches!(res, Err(Error::UnclaimedDomain(_))));
+}
+
+#[tokio::test]
+async fn resolves_domain_through_well_known() {
+    let s = Services::start().await;
+    s.did(DID, HANDLE).await;
+    s.web(
+        DOMAIN,
+        "/.well-known/atpage.json",
+        &json!({ "did": DID }).to_string(),
+    )
+    .await;
+    s.record(
+        DID,
+        SITE_COLLECTION,
+        SITE_RKEY,
+        json!({ "assets": [], "pages": [], "domains": [DOMAIN] }),
+    )
+    .await;
+
+    let id = domain::solve(&s.host(), DOMAIN).await.unwrap();
+
+    assert_eq!(id.did, DID);
+}
+
+#[tokio::test]
+async fn refuses_addresses_and_local_names() {
+    let s = Services::start().await;
+
+    for domain in [
+        "127.0.0.1",
+        "10.1",
+        "localhost",
+        "example.com:8080",
+        "[::1]",
+    ] {
+        let res = domain::solve(&s.host(), domain).await;
+
+        assert!(matches!(res, Err(Error::UnclaimedDomain(_))), "{}", domain);
+    }
+
+    assert!(s.server.received_requests().await.unwrap().is_empty());
+}
@@ -0,0 +1,136 @@
+mod common;
+
+use atpage_renderer::{atproto::Error, error, identity};
+use common::{Services, DID, HANDLE};
+use serde_json::json;
+
+#[tokio::test]
+async fn resolves_did() {
+    let s = Services::start().await;
+    s.did(DID, HANDLE).await;
+
+    let id = identity::solve(&s.host(), DID).await.unwrap();
+
+    assert_eq!(id.did, DID);
+    assert_eq!(id.pds, s.server.uri());
+}
+
+#[tokio::test]
+async fn resolves_handle() {
+    let s = Services::start().await;
+    s.did(DID, HANDLE).await;
+    s.handle(HANDLE, &[DID]).await;
+
+    let id = identity::solve(&s.host(), HANDLE).await.unwrap();
+
+    assert_eq!(id.did, DID);
+}
+
+#[tokio::test]
+async fn resolves_handle_through_well_known() {
+    let s = Services::start().await;
+    s.did(DID, HANDLE).await;
+    s.web(HANDLE, "/.well-known/atproto-did", DID).await;
+
+    let id = identity::solve(&s.host(), HANDLE).await.unwrap();
+
+    assert_eq!(id.did, DID);
+}
+
+#[tokio::test]
+async fn resolves_did_web() {
+    let s = Services::start().await;
+    let did = "did:web:alice.test";
+    let doc = json!({
+        "id": did,
+        "service": [{
+            "id": "#atproto_pds",
+            "type": "AtprotoPersonalDataServer",
+            "serviceEndpoint": s.server.uri(),
+        }],
+    });
+    s.web("alice.test", "/.well-known/did.json", &doc.to_string())
+        .await;
+
+    let id = identity::solve(&s.host(), did).await.unwrap();
+
+    assert_eq!(id.did, did);
+    assert_eq!(id.pds, s.server.uri());
+}
+
+#[tokio::test]
+async fn refuses_handle_not_claimed_back() {
+    let s = Services::start().await;
+    s.did(DID, "mallory.test").await;
+    s.handle(HANDLE, &[DID]).await;
+
+    let res = identity::solve(&s.host(), HANDLE).await;
+
+    assert!(matches!(res, Err(Error::HandleMismatch(_))));
+}
+
+#[tokio::test]
+async fn refuses_ambiguous_handle() {
+    let s = Services::start().await;
+    s.did(DID, HANDLE).await;
+    s.handle(HANDLE, &[DID, "did:plc:someoneelse"]).await;
+
+    let res = identity::solve(&s.host(), HANDLE).await;
+
+    assert!(matches!(res, Err(Error::NoDIDFound(_))));
+}
+
+#[tokio::test]
+async fn refuses_unsupported_did() {
+    let s = Services::start().await;
+
+    let res = identity::solve(&s.host(), "did:key:z6Mkabc").await;
+
+    assert!(matches!(res, Err(Error::UnsupportedDID(_))));
+}
+
+#[tokio::test]
+async fn refuses_internal_hosts() {
+    let s = Services::start().await;
+
+    for id in [
+        "localhost",
+        "127.0.0.1",
+        "metadata.internal",
+        "printer.local",
+        "did:web:localhost",
+        "did:web:10.0.0.1",
+        "did:web:example.com%3A8080",
+    ] {
+        let res = identity::solve(&s.host(), id).await;
+
+        assert!(
+            matches!(res, Err(Error::NoDIDFound(_) | Error::UnsupportedDID(_))),
+            "{}",
+            id
+        );
+    }
+
+    assert!(s.server.received_requests().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn reports_failing_directory() {
+    let s = Services::start().await;
+    s.did(DID, HANDLE).await;
+    s.failing(&format!("/{}", DID)).await;
+
+    let res = identity::solve(&s.host(), DID).await;
+
+    assert!(matches!(res, Err(Error::FetchError(_))));
+    assert_eq!(error::status(&res.unwrap_err()), 502);
+}
+
+#[tokio::test]
+async fn reports_missing_did() {
+    let s = Services::start().await;
+
+    let res = identity::solve(&s.host(), DID).await;
+
+    assert!(matches!(res, Err(Error::NoDIDFound(_))));
+}
@@ -0,0 +1,597 @@
+mod common;
+
+use atpage_renderer::{
+    atproto::Error,
+    conditional::Conditions,
+    host::{Host, Native},
+    serve::{self, Body, Request, Site},
+};
+use common::{blob_ref, cid, Services, DID, HANDLE};
+use futures::TryStreamExt;
+use serde_json::{json, Value};
+use shared::atproto::{CHUNKED_COLLECTION, PAGE_COLLECTION, SITE_COLLECTION, SITE_RKEY};
+
+const PAGE: &[u8] = b"<!DOCTYPE html><html><body><h1>Hello</h1><script>hi()</script></body></html>";
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+const FILE: &[u8] = b"0123456789abcdefghij!";
+const ORIGIN: &str = "https://atpage.example";
+
+type Response = serve::Response<<Native as Host>::BlobStream>;
+
+/// site publishes a small site on s: an index page, a post and a standalone PNG.
+async fn site(s: &Services) {
+    site_with(s, json!({})).await;
+}
+
+/// site_with publishes the site of site, with the fields of extra added to its site record.
+async fn site_with(s: &Services, extra: Value) {
+    s.did(DID, HANDLE).await;
+    s.handle(HANDLE, &[DID]).await;
+    s.not_found().await;
+
+    let page = json!({
+        "title": "Hello",
+        "content": blob_ref(PAGE, "text/html"),
+        "updatedAt": "2024-11-06T08:49:37.000+01:00",
+    });
+    s.record(DID, PAGE_COLLECTION, "index", page.clone()).await;
+    s.record(DID, PAGE_COLLECTION, "post", page).await;
+    s.blob(DID, &cid(PAGE), PAGE).await;
+    s.blob(DID, &cid(PNG), PNG).await;
+
+    let mut record = json!({
+        "assets": [{ "path": "/logo", "blob": blob_ref(PNG, "image/png") }],
+        "pages": [
+            { "path": "/index.html", "rkey": "index" },
+            { "path": "/blog/post.html", "rkey": "post" },
+        ],
+        "headers": [{ "name": "Permissions-Policy", "value": "camera=()" }],
+    });
+    for (k, v) in extra.as_object().unwrap() {
+        record[k] = v.clone();
+    }
+
+    s.record(DID, SITE_COLLECTION, SITE_RKEY, record).await;
+}
+
+/// get routes a request for path, with the site served by HANDLE if site is set.
+async fn get(s: &Services, path: &str, site: bool, conditions: Conditions) -> Response {
+    let req = Request {
+        origin: ORIGIN.to_string(),
+        path: path.to_string(),
+        site: site.then(|| Site::Repo(HANDLE.to_string())),
+        conditions,
+    };
+
+    serve::route(&s.host(), req).await
+}
+
+fn header<'a>(resp: &'a Response, name: &str) -> Option<&'a str> {
+    resp.headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+/// read returns the body of resp, reading streamed blobs and chunked files from s to their end.
+async fn read(s: &Services, resp: Response) -> Result<Vec<u8>, Error> {
+    match resp.body {
+        Body::Empty => Ok(vec![]),
+        Body::Bytes(data) => Ok(data),
+        Body::Stream(content) => content.try_concat().await,
+        Body::Chunks { pds, did, parts } => {
+            let host = s.host();
+            serve::chunks(&host, pds, did, parts).try_concat().await
+        }
+    }
+}
+
+async fn body(s: &Services, resp: Response) -> Vec<u8> {
+    read(s, resp).await.unwrap()
+}
+
+#[tokio::test]
+async fn serves_pages_by_path() {
+    let s = Services::start().await;
+    site(&s).await;
+
+    let resp = get(&s, "/blog/post", true, Conditions::default()).await;
+
+    assert_eq!(resp.status, 200);
+    assert_eq!(
+        header(&resp, "Content-Type"),
+        Some("text/html; charset=utf-8")
+    );
+    assert_eq!(
+        header(&resp, "Last-Modified"),
+        Some("Wed, 06 Nov 2024 07:49:37 GMT")
+    );
+    assert_eq!(body(&s, resp).await, PAGE);
+}
+
+#[tokio::test]
+async fn revalidates_pages() {
+    let s = Services::start().await;
+    site(&s).await;
+
+    let etag = format!("W/\"{}\"", cid(PAGE));
+    let resp = get(&s, "/", true, Conditions::new(None, Some(&etag))).await;
+
+    assert_eq!(resp.status, 304);
+    assert!(body(&s, resp).await.is_empty());
+}
+
+#[tokio::test]
+async fn reports_missing_records() {
+    let s = Services::start().await;
+    site(&s).await;
+
+    let resp = get(&s, "/nope", true, Conditions::default()).await;
+    assert_eq!(resp.status, 404);
+
+    let resp = get(
+        &s,
+        &format!("/at/{}/{}/missing", DID, PAGE_COLLECTION),
+        false,
+        Conditions::default(),
+    )
+    .await;
+    assert_eq!(resp.status, 404);
+}
+
+#[tokio::test]
+async fn reports_failing_pds() {
+    let s = Services::start().await;
+    site(&s).await;
+    s.failing("/xrpc/com.atproto.repo.getRecord").await;
+
+    let resp = get(
+        &s,
+        &format!("/at/{}/{}/index", DID, PAGE_COLLECTION),
+        false,
+        Conditions::default(),
+    )
+    .await;
+    assert_eq!(resp.status, 502);
+
+    let resp = get(&s, "/", true, Conditions::default()).await;
+    assert_eq!(resp.status, 502);
+}
+
+#[tokio::test]
+async fn serves_custom_error_pages() {
+    let s = Services::start().await;
+    site_with(
+        &s,
+        json!({
+            "pages": [
+                { "path": "/index.html", "rkey": "index" },
+                { "path": "/broken.html", "rkey": "broken" },
+            ],
+            "notFound": "not-found",
+            "serverError": "server-error",
+        }),
+    )
+    .await;
+
+    let missing = b"<html><body>Nothing here</body></html>";
+    let failing = b"<html><body>Try again later</body></html>";
+    for (rkey, content) in [("not-found", &missing[..]), ("server-error", &failing[..])] {
+        let page = json!({ "title": rkey, "content": blob_ref(content, "text/html") });
+        s.record(DID, PAGE_COLLECTION, rkey, page).await;
+        s.blob(DID, &cid(content), content).await;
+    }
+    // a page without content is a PDS serving something unusable
+    s.record(DID, PAGE_COLLECTION, "broken", json!({ "title": "Broken" }))
+        .await;
+
+    let resp = get(&s, "/nope", true, Conditions::default()).await;
+    assert_eq!(resp.status, 404);
+    assert_eq!(
+        header(&resp, "Content-Type"),
+        Some("text/html; charset=utf-8")
+    );
+    assert_eq!(body(&s, resp).await, missing);
+
+    let resp = get(&s, "/broken", true, Conditions::default()).await;
+    assert_eq!(resp.status, 502);
+    assert_eq!(body(&s, resp).await, failing);
+}
+
+#[tokio::test]
+async fn falls_back_when_error_pages_fail() {
+    let s = Services::start().await;
+    site_with(&s, json!({ "notFound": "not-found" })).await;
+
+    // the error page's own record is missing too
+    let resp = get(&s, "/nope", true, Conditions::default()).await;
+
+    assert_eq!(resp.status, 404);
+    assert!(String::from_utf8_lossy(&body(&s, resp).await).contains("Not Found"));
+}
+
+#[tokio::test]
+async fn refuses_tampered_blobs() {
+    let s = Services::start().await;
+    site(&s).await;
+    s.blob(DID, &cid(b"something else"), PNG).await;
+
+    let resp = get(
+        &s,
+        &format!("/at/{}/blobs/{}", DID, cid(b"something else")),
+        false,
+        Conditions::default(),
+    )
+    .await;
+
+    // the blob is streamed before it's all read, so it can only be cut short
+    assert_eq!(resp.status, 200);
+    assert!(matches!(
+        read(&s, resp).await,
+        Err(Error::VerificationFailed(_))
+    ));
+}
+
+#[tokio::test]
+async fn streams_blobs() {
+    let s = Services::start().await;
+    site(&s).await;
+
+    let resp = get(
+        &s,
+        &format!("/at/{}/blobs/{}", DID, cid(PNG)),
+        false,
+        Conditions::default(),
+    )
+    .await;
+
+    assert_eq!(resp.status, 200);
+    assert!(matches!(resp.body, Body::Stream(_)));
+    assert_eq!(body(&s, resp).await, PNG);
+}
+
+#[tokio::test]
+async fn types_blobs() {
+    let s = Services::start().await;
+    site(&s).await;
+
+    // the PDS serves everything as application/octet-stream
+    let resp = get(&s, "/logo", true, Conditions::default()).await;
+    assert_eq!(header(&resp, "Content-Type"), Some("image/png"));
+
+    let resp = get(
+        &s,
+        &format!("/at/{}/blobs/{}", DID, cid(PNG)),
+        false,
+        Conditions::default(),
+    )
+    .await;
+    assert_eq!(header(&resp, "Content-Type"), Some("image/png"));
+}
+
+#[tokio::test]
+async fn serves_ranges_of_blobs() {
+    let s = Services::start().await;
+    site(&s).await;
+
+    let resp = get(&s, "/logo", true, Conditions::new(Some("bytes=1-3"), None)).await;
+    assert_eq!(resp.status, 206);
+    assert_eq!(
+        header(&resp, "Content-Range"),
+        Some(format!("bytes 1-3/{}", PNG.len()).as_str())
+    );
+    assert_eq!(body(&s, resp).await, b"PNG");
+
+    let resp = get(&s, "/logo", true, Conditions::new(Some("bytes=100-"), None)).await;
+    assert_eq!(resp.status, 416);
+}
+
+#[tokio::test]
+async fn forwards_ranges_to_the_pds() {
+    const MP3: &[u8] = b"ID3\x04\0\0\0\0\0\0 some audio";
+
+    let s = Services::start().await;
+    site(&s).await;
+    s.blob_ranges(DID, &cid(MP3), MP3).await;
+
+    let path = format!("/at/{}/blobs/{}", DID, cid(MP3));
+
+    let resp = get(&s, &path, false, Conditions::new(Some("bytes=11-15"), None)).await;
+    assert_eq!(resp.status, 206);
+    assert_eq!(
+        header(&resp, "Content-Range"),
+        Some(format!("bytes 11-15/{}", MP3.len()).as_str())
+    );
+    assert_eq!(body(&s, resp).await, b"some ");
+
+    let resp = get(
+        &s,
+        &path,
+        false,
+        Conditions::new(Some("bytes=100-200"), None),
+    )
+    .await;
+    assert_eq!(resp.status, 416);
+    assert_eq!(
+        header(&resp, "Content-Range"),
+        Some(format!("bytes */{}", MP3.len()).as_str())
+    );
+
+    let ranges = s
+        .server
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .filter(|r| r.headers.contains_key("Range"))
+        .count();
+    assert_eq!(ranges, 2);
+}
+
+/// chunked publishes FILE on s as a chunked file at /big.txt, split in chunks of 4 bytes of
+/// which the ones in tampered are served with other content.
+async fn chunked(s: &Services, tampered: &[usize]) {
+    let rkey = cid(FILE);
+
+    site_with(
+        s,
+        json!({ "assets": [{ "path": "/big.txt", "chunked": rkey }] }),
+    )
+    .await;
+
+    let chunks = FILE.chunks(4).collect::<Vec<&[u8]>>();
+    for (i, chunk) in chunks.iter().enumerate() {
+        match tampered.contains(&i) {
+            true => s.blob(DID, &cid(chunk), b"XXXX").await,
+            false => s.blob(DID, &cid(chunk), chunk).await,
+        }
+    }
+
+    s.record(
+        DID,
+        CHUNKED_COLLECTION,
+        &rkey,
+        json!({
+            "mimeType": "text/plain",
+            "size": FILE.len(),
+            "chunks": chunks
+                .iter()
+                .map(|c| blob_ref(c, "application/octet-stream"))
+                .collect::<Vec<Value>>(),
+        }),
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn reassembles_chunked_files() {
+    let s = Services::start().await;
+    chunked(&s, &[]).await;
+
+    let resp = get(&s, "/big.txt", true, Conditions::default()).await;
+
+    assert_eq!(resp.status, 200);
+    assert_eq!(header(&resp, "Content-Type"), Some("text/plain"));
+    assert_eq!(
+        header(&resp, "Content-Length"),
+        Some(FILE.len().to_string().as_str())
+    );
+    assert_eq!(body(&s, resp).await, FILE);
+
+    let resp = get(
+        &s,
+        &format!("/at/{}/{}/{}", DID, CHUNKED_COLLECTION, cid(FILE)),
+        false,
+        Conditions::default(),
+    )
+    .await;
+    assert_eq!(body(&s, resp).await, FILE);
+}
+
+#[tokio::test]
+async fn serves_ranges_across_chunks() {
+    let s = Services::start().await;
+    chunked(&s, &[]).await;
+
+    let resp = get(
+        &s,
+        "/big.txt",
+        true,
+        Conditions::new(Some("bytes=2-9"), None),
+    )
+    .await;
+
+    assert_eq!(resp.status, 206);
+    assert_eq!(
+        header(&resp, "Content-Range"),
+        Some(format!("bytes 2-9/{}", FILE.len()).as_str())
+    );
+    assert_eq!(body(&s, resp).await, &FILE[2..=9]);
+}
+
+#[tokio::test]
+async fn refuses_tampered_chunks() {
+    let s = Services::start().await;
+    chunked(&s, &[1]).await;
+
+    let resp = get(&s, "/big.txt", true, Conditions::default()).await;
+
+    // chunks are read as the file is sent, so it can only be cut short
+    assert_eq!(resp.status, 200);
+    assert!(matches!(
+        read(&s, resp).await,
+        Err(Error::VerificationFailed(_))
+    ));
+}
+
+#[tokio::test]
+async fn reports_missing_chunks() {
+    let s = Services::start().await;
+    chunked(&s, &[]).await;
+
+    // a record listing a chunk the PDS doesn't have, after the ones it has
+    let missing = b"gone";
+    let chunks = FILE
+        .chunks(4)
+        .chain([&missing[..]])
+        .map(|c| blob_ref(c, "application/octet-stream"))
+        .collect::<Vec<Value>>();
+    let rkey = "bafkreimissingchunk";
+    s.record(
+        DID,
+        CHUNKED_COLLECTION,
+        rkey,
+        json!({ "mimeType": "text/plain", "size": FILE.len() + missing.len(), "chunks": chunks }),
+    )
+    .await;
+
+    let resp = get(
+        &s,
+        &format!("/at/{}/{}/{}", DID, CHUNKED_COLLECTION, rkey),
+        false,
+        Conditions::default(),
+    )
+    .await;
+
+    assert_eq!(resp.status, 200);
+    assert!(read(&s, resp).await.is_err());
+}
+
+#[tokio::test]
+async fn refuses_malformed_chunked_records() {
+    let s = Services::start().await;
+    chunked(&s, &[]).await;
+
+    // the chunks don't add up to the size recorded
+    let rkey = "bafkreibadsize";
+    s.record(
+        DID,
+        CHUNKED_COLLECTION,
+        rkey,
+        json!({
+            "mimeType": "text/plain",
+            "size": 3,
+            "chunks": [blob_ref(&FILE[..4], "application/octet-stream")],
+        }),
+    )
+    .await;
+
+    let resp = get(
+        &s,
+        &format!("/at/{}/{}/{}", DID, CHUNKED_COLLECTION, rkey),
+        false,
+        Conditions::default(),
+    )
+    .await;
+
+    assert_eq!(resp.status, 502);
+}
+
+#[tokio::test]
+async fn trusts_the_site() {
+    let s = Services::start().await;
+    site(&s).await;
+
+    let resp = get(&s, "/", true, Conditions::default()).await;
+
+    assert!(header(&resp, "Content-Security-Policy")
+        .unwrap()
+        .contains(&format!("{}/at/{}/", ORIGIN, DID)));
+    assert_eq!(header(&resp, "Permissions-Policy"), Some("camera=()"));
+    assert_eq!(body(&s, resp).await, PAGE);
+}
+
+#[tokio::test]
+async fn sanitizes_untrusted_pages() {
+    let s = Services::start().await;
+    site(&s).await;
+
+    let resp = get(
+        &s,
+        &format!("/at/{}/{}/index", DID, PAGE_COLLECTION),
+        false,
+        Conditions::default(),
+    )
+    .await;
+
+    assert_eq!(resp.status, 200);
+    assert!(header(&resp, "Content-Security-Policy")
+        .unwrap()
+        .contains("script-src 'none'"));
+    assert!(!String::from_utf8_lossy(&body(&s, resp).await).contains("<script>"));
+}
+
+/// allows returns true if the script-src directive of csp lets a page load the script at url.
+fn allows(csp: &str, url: &str) -> bool {
+    csp.split(';')
+        .filter_map(|d| d.trim().strip_prefix("script-src "))
+        .flat_map(|d| d.split_whitespace())
+        .filter(|s| !s.starts_with('\''))
+        .any(|s| match s.ends_with('/') {
+            true => url.starts_with(s),
+            false => url == s,
+        })
+}
+
+#[tokio::test]
+async fn allows_the_scripts_pages_link_to() {
+    const JS: &[u8] = b"console.log(1)";
+
+    let s = Services::start().await;
+    s.did(DID, HANDLE).await;
+    s.handle(HANDLE, &[DID]).await;
+    s.not_found().await;
+
+    // pages published before links were made by DID point to blobs by handle
+    let scripts = [
+        format!("/at/{}/blobs/{}", HANDLE, cid(JS)),
+        format!("/at/{}/blobs/{}", DID, cid(JS)),
+        "/app.js".to_string(),
+    ];
+    let page = scripts
+        .iter()
+        .map(|src| format!("<script src=\"{}\"></script>", src))
+        .collect::<String>();
+
+    s.record(
+        DID,
+        PAGE_COLLECTION,
+        "index",
+        json!({ "title": "App", "content": blob_ref(page.as_bytes(), "text/html") }),
+    )
+    .await;
+    s.blob(DID, &cid(page.as_bytes()), page.as_bytes()).await;
+    s.record(
+        DID,
+        SITE_COLLECTION,
+        SITE_RKEY,
+        json!({
+            "assets": [{ "path": "/app.js", "blob": blob_ref(JS, "text/javascript") }],
+            "pages": [{ "path": "/index.html", "rkey": "index" }],
+        }),
+    )
+    .await;
+
+    for path in [
+        "/".to_string(),
+        format!("/at/{}/{}/index", DID, PAGE_COLLECTION),
+    ] {
+        let resp = get(&s, &path, true, Conditions::default()).await;
+        assert_eq!(resp.status, 200);
+
+        let csp = header(&resp, "Content-Security-Policy").unwrap();
+        for src in scripts.iter() {
+            assert!(
+                allows(csp, &format!("{}{}", ORIGIN, src)),
+                "{}: {}",
+                src,
+                csp
+            );
+        }
+
+        assert!(!allows(
+            csp,
+            &format!("{}/at/did:plc:someoneelse/blobs/{}", ORIGIN, cid(JS))
+        ));
+    }
+}
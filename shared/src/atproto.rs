@@ -1,3 +1,31 @@
+/// NSID of the collection holding pages.
+pub const PAGE_COLLECTION: &str = "industries.geesawra.atpage";
+
+/// NSID of the collection holding site-level records.
+pub const SITE_COLLECTION: &str = "industries.geesawra.atpage.site";
+
+/// Record key of the one site record each repo holds.
+pub const SITE_RKEY: &str = "self";
+
+/// NSID of the collection holding files split in several blobs, too big to fit in a single one.
+pub const CHUNKED_COLLECTION: &str = "industries.geesawra.atpage.chunked";
+
+/// Response headers a site record may set on everything the site serves.
+pub const SITE_HEADERS: &[&str] = &[
+    "Content-Security-Policy",
+    "Content-Security-Policy-Report-Only",
+    "Referrer-Policy",
+    "Permissions-Policy",
+    "X-Frame-Options",
+    "X-Content-Type-Options",
+    "Cross-Origin-Opener-Policy",
+    "Cross-Origin-Embedder-Policy",
+    "Cross-Origin-Resource-Policy",
+];
+
+/// Pseudo-collection used in AT URLs to address standalone assets by their original path.
+pub const ASSETS: &str = "assets";
+
 #[derive(Debug)]
 pub enum Error {
     WrongComponentsAmount(usize),
@@ -9,7 +37,10 @@ impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Error::WrongComponentsAmount(amt) => {
-                write!(f, "AT URL components must be 3, found {amt}")
+                write!(
+                    f,
+                    "AT URLs must be repo/collection/key, with slashes in the key only for assets, found {amt} components"
+                )
             }
         }
     }
@@ -21,6 +52,7 @@ pub struct ATURL {
     pub collection: String,
     pub key: String,
     pub blob: bool,
+    pub asset: bool,
     pub needs_resolution: bool,
 }
 
@@ -30,17 +62,18 @@ impl TryFrom<String> for ATURL {
     fn try_from(value: String) -> Result<Self, Error> {
         let value = value.strip_prefix("at://").unwrap_or(&value);
 
-        let comp = value.split("/").collect::<Vec<&str>>();
+        let comp = value.splitn(3, "/").collect::<Vec<&str>>();
 
-        if comp.len() != 3 {
-            return Err(Error::WrongComponentsAmount(comp.len()));
+        if comp.len() != 3 || (comp[1] != ASSETS && comp[2].contains("/")) {
+            return Err(Error::WrongComponentsAmount(value.split("/").count()));
         }
 
         Ok(ATURL {
             did: comp[0].to_string(),
             collection: comp[1].to_string(),
             key: comp[2].to_string(),
-            blob: comp[1].to_string() == "blobs",
+            blob: comp[1] == "blobs",
+            asset: comp[1] == ASSETS,
             needs_resolution: !comp[0].starts_with("did:"),
         })
     }
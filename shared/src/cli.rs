@@ -1,6 +1,6 @@
 use clap::Parser;
 
-const DEFAULT_PDS: &'static str = "https://bsky.app";
+const DEFAULT_PDS: &str = "https://bsky.app";
 
 #[derive(Parser, Debug, Clone)]
 pub struct LoginData {
@@ -33,6 +33,30 @@ pub enum Command {
         /// File containing additional HTML tags to be placed in the static index.html <head> file.
         #[arg(long)]
         extra_head: Option<String>,
+
+        /// Gitignore-style glob of files to leave out of the website, can be repeated.
+        /// Patterns listed in .atpageignore files inside src are always applied.
+        #[arg(long)]
+        exclude: Vec<String>,
+
+        /// Glob or directory of files to publish even if no page references them, like robots.txt
+        /// or favicon.ico, can be repeated.
+        #[arg(long)]
+        include: Vec<String>,
+
+        /// Maximum size of a blob in bytes accepted by the PDS, 5 MiB by default.
+        #[arg(long, env = "ATPAGE_MAX_BLOB_SIZE")]
+        max_blob_size: Option<usize>,
+
+        /// Security header served along with every page and file, as "Name: value", can be repeated.
+        /// Only Content-Security-Policy, Referrer-Policy, Permissions-Policy and the like are allowed.
+        #[arg(long)]
+        header: Vec<String>,
+
+        /// Domain the website may be served at by renderers and gateways looking sites up by
+        /// domain, can be repeated.
+        #[arg(long)]
+        domain: Vec<String>,
     },
 
     /// Deletes the industries.geesawra.website from the configured PDS for the logged-in user.
@@ -41,7 +65,8 @@ pub enum Command {
     /// Compiles atpage_renderer, useful for local development.
     Compile {
         /// AT URI containing a industries.geesawra.website collection.
-        at_uri: String,
+        /// Without it, the renderer serves the site claiming the domain it's deployed at.
+        at_uri: Option<String>,
 
         /// File containing additional HTML tags to be placed in the static index.html <head> file.
         #[arg(long)]
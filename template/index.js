@@ -1,18 +1,30 @@
-var homepage = "REPLACE_ME";
+// the service worker serves the site at its original paths, so once it's in control
+// reloading the current URL is enough
+var homepage =
+  window.location.pathname + window.location.search + window.location.hash;
 
-let current_location = window.location.pathname;
+// reloading lands back here when the service worker doesn't serve this URL, which must only
+// happen once instead of looping forever
+var reloaded_key = "atpage-reloaded";
 
-if (
-  current_location != null &&
-  current_location != "/" &&
-  current_location.startsWith("/at/")
-) {
-  homepage = current_location;
-}
+function reload() {
+  try {
+    if (sessionStorage.getItem(reloaded_key) === homepage) {
+      console.error(`The service worker doesn't serve ${homepage}`);
+      return;
+    }
+
+    sessionStorage.setItem(reloaded_key, homepage);
+  } catch (error) {
+    console.error(`Can't reload safely without session storage: ${error}`);
+    return;
+  }
 
-navigator.serviceWorker.ready.then(() => {
   window.location.replace(homepage);
-});
+}
+
+// ready resolves once a service worker is active, whether it just got installed or was already
+navigator.serviceWorker.ready.then(reload);
 
 var sw_path = "/sw.js";
 
@@ -21,14 +33,9 @@ if (navigator.userAgent.toLowerCase().indexOf("firefox") !== -1) {
 }
 if ("serviceWorker" in navigator) {
   try {
-    const registration = await navigator.serviceWorker.register(sw_path, {
+    await navigator.serviceWorker.register(sw_path, {
       type: "module",
     });
-
-    if (registration.active) {
-      // we're already installed and active, redirect
-      window.location.replace(homepage);
-    }
   } catch (error) {
     console.error(`Registration failed with ${error}`);
   }
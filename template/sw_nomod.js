@@ -1,6 +1,31 @@
 importScripts("/nomod/atpage_renderer.js");
 
-const { resolve, is_at } = wasm_bindgen;
+const { resolve, is_at, configure } = wasm_bindgen;
+
+// Renderer settings, see atpage_renderer/src/config.rs.
+const config = {
+  // Handle or DID of the site served at plain paths, like /blog/post.html.
+  site: "REPLACE_ME",
+  // When site is null, serve the site claiming the requested domain through an
+  // _atpage TXT record or /.well-known/atpage.json instead.
+  domains: false,
+  // Handles or DIDs whose pages may run scripts, pages of any other repo are
+  // sanitized. The site is always trusted.
+  trusted: [],
+  // Only render records proven to come from the site's repo.
+  verify: false,
+  // DNS-over-HTTPS JSON endpoint used to resolve handles.
+  dohResolver: "https://cloudflare-dns.com/dns-query",
+  // XRPC service to fall back to when a handle can't be resolved otherwise,
+  // e.g. "https://bsky.social".
+  handleResolver: null,
+  // PLC directory did:plc documents are fetched from.
+  plcDirectory: "https://plc.directory",
+  // Seconds resolved handles and DIDs are cached for.
+  identityTtl: 3600,
+  // Bytes of records and blobs kept around for offline use.
+  cacheSize: 100 * 1024 * 1024,
+};
 
 var initialized = false;
 
@@ -20,6 +45,7 @@ self.addEventListener("fetch", (event) => {
           await wasm_bindgen({
             module_or_path: "/nomod/atpage_renderer_bg.wasm",
           }).then(() => {
+            configure(config);
             initialized = true;
           });
         }
@@ -31,7 +57,10 @@ self.addEventListener("fetch", (event) => {
         return await resolve(event);
       } catch (error) {
         console.log("atpage fetch error: ", error, event);
-        return;
+        return new Response("atpage renderer error: " + error, {
+          status: 500,
+          headers: { "Content-Type": "text/plain; charset=utf-8" },
+        });
       }
     })(),
   );
@@ -6,21 +6,38 @@ use xshell::{cmd, Shell};
 fn main() -> Result<()> {
     let is_debug_build = {
         if let Ok(maybe_debug_build) = std::env::var("DEBUG_BUILD") {
-            match maybe_debug_build.to_lowercase().parse() {
-                Ok(v) => v,
-                Err(_) => false,
-            }
+            maybe_debug_build.to_lowercase().parse().unwrap_or_default()
         } else {
             false
         }
     };
 
     match shared::cli::Command::parse() {
-        shared::cli::Command::Post { login_data, src , extra_head} => {
+        shared::cli::Command::Post {
+            login_data,
+            src,
+            extra_head,
+            exclude,
+            include,
+            max_blob_size,
+            header,
+            domain,
+        } => {
             compile_all(is_debug_build)?;
 
             println!("Posting contents of {src} as an atpage website...");
-            assemble(publish(login_data, src)?, extra_head)?;
+            assemble(
+                Some(publish(
+                    login_data,
+                    src,
+                    exclude,
+                    include,
+                    max_blob_size,
+                    header,
+                    domain,
+                )?),
+                extra_head,
+            )?;
 
             println!("Website posted! Now publish the contents of the `public` folder somewhere and have fun :)");
 
@@ -31,7 +48,7 @@ fn main() -> Result<()> {
             println!("DEBUG_BUILD: {}", is_debug_build);
 
             compile_all(is_debug_build)?;
-            if !at_uri.starts_with("at://") {
+            if at_uri.as_ref().is_some_and(|u| !u.starts_with("at://")) {
                 return Err(anyhow!("aturi argument must be a valid AT URI"));
             }
 
@@ -70,16 +87,35 @@ fn compile_all(is_debug_build: bool) -> Result<()> {
     Ok(())
 }
 
-fn publish(ld: LoginData, src: String) -> Result<String> {
+fn publish(
+    ld: LoginData,
+    src: String,
+    exclude: Vec<String>,
+    include: Vec<String>,
+    max_blob_size: Option<usize>,
+    header: Vec<String>,
+    domain: Vec<String>,
+) -> Result<String> {
     let sh = Shell::new()?;
 
     // compile atpage_publisher
     cmd!(sh, "cargo build --release --package atpage_publisher").run()?;
 
     let (username, password, pds) = (ld.username, ld.password, ld.pds);
+    let exclude = exclude
+        .into_iter()
+        .flat_map(|e| ["--exclude".to_string(), e]);
+    let include = include
+        .into_iter()
+        .flat_map(|i| ["--include".to_string(), i]);
+    let max_blob_size = max_blob_size
+        .into_iter()
+        .flat_map(|m| ["--max-blob-size".to_string(), m.to_string()]);
+    let header = header.into_iter().flat_map(|h| ["--header".to_string(), h]);
+    let domain = domain.into_iter().flat_map(|d| ["--domain".to_string(), d]);
     let res = cmd!(
         sh,
-        "target/release/atpage_publisher post --username {username} --password {password} --src {src} --pds {pds}"
+        "target/release/atpage_publisher post --username {username} --password {password} --src {src} --pds {pds} {exclude...} {include...} {max_blob_size...} {header...} {domain...}"
     )
     .read()?;
 
@@ -100,8 +136,16 @@ fn nuke(ld: LoginData) -> Result<()> {
     .run()?)
 }
 
-fn assemble(at_uri: String, extra_head: Option<String>) -> Result<()> {
-    let at_uri = at_uri.replace("at://", "/at/");
+fn assemble(at_uri: Option<String>, extra_head: Option<String>) -> Result<()> {
+    // the repo holding the index page is the site served at plain paths, without one the
+    // renderer looks the site up by domain
+    let site = at_uri.map(|u| {
+        u.trim_start_matches("at://")
+            .split("/")
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    });
 
     let sh = Shell::new()?;
 
@@ -109,11 +153,18 @@ fn assemble(at_uri: String, extra_head: Option<String>) -> Result<()> {
         sh.copy_file(i, "public/")?;
     }
 
-    let ijs = sh.read_file("template/index.js")?;
+    for sw in ["sw.js", "sw_nomod.js"] {
+        let swjs = sh.read_file(format!("template/{}", sw))?;
 
-    let ijs = ijs.replace("REPLACE_ME", &at_uri);
+        let swjs = match &site {
+            Some(site) => swjs.replace("REPLACE_ME", site),
+            None => swjs
+                .replace("\"REPLACE_ME\"", "null")
+                .replace("domains: false", "domains: true"),
+        };
 
-    sh.write_file("public/index.js", ijs)?;
+        sh.write_file(format!("public/{}", sw), swjs)?;
+    }
 
     if let Some(of) = extra_head {
         let ofc = sh.read_file(of)?;